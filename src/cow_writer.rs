@@ -0,0 +1,59 @@
+use std::{borrow::Cow, fmt};
+
+/// A `fmt::Write` which compares the written string with the input, and allocates a `String` only after they differ.
+pub(crate) struct CowWriter<'a> {
+    input: &'a str,
+    start: usize,
+    /// `input[start..end]` is what has been written.
+    end:   usize,
+    owned: Option<String>,
+}
+
+impl<'a> CowWriter<'a> {
+    /// Create a writer which expects the output to be the same as `input` without leading whitespace.
+    #[inline]
+    pub(crate) fn new(input: &'a str) -> Self {
+        let start = input.len() - input.trim_start().len();
+
+        CowWriter {
+            input,
+            start,
+            end: start,
+            owned: None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn into_cow(self) -> Cow<'a, str> {
+        match self.owned {
+            Some(owned) => Cow::Owned(owned),
+            None => Cow::Borrowed(&self.input[self.start..self.end]),
+        }
+    }
+}
+
+impl<'a> fmt::Write for CowWriter<'a> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.owned.as_mut() {
+            Some(owned) => owned.push_str(s),
+            None => {
+                if self.input.as_bytes()[self.end..].starts_with(s.as_bytes()) {
+                    self.end += s.len();
+                } else {
+                    let written = &self.input[self.start..self.end];
+
+                    let mut owned =
+                        String::with_capacity(self.input.len() + (self.input.len() >> 3));
+
+                    owned.push_str(written);
+                    owned.push_str(s);
+
+                    self.owned = Some(owned);
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
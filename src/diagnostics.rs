@@ -0,0 +1,221 @@
+use std::{fmt, ops::Range};
+
+use crate::{
+    tag_names::{is_optional_end_tag_name, is_void_tag_name},
+    tokenizer::{find_comment_end, Token, Tokenizer},
+    AutoPEngine,
+};
+
+/// The kind of a `Diagnostic`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum DiagnosticKind {
+    /// A block-level element which is never closed. Elements whose end tags may be omitted, such as `<p>` and `<li>`, are not reported.
+    UnclosedElement,
+    /// An end tag which does not close any open element.
+    StrayEndTag,
+    /// A block-level element inside an inline element, such as `<div>` in `<b>`.
+    BlockInInline,
+    /// An element whose inner HTML should be preserved, such as `<pre>`, which is never closed, so its inner HTML gets `auto_p`ed.
+    UnclosedPreservedElement,
+    /// A comment which is never closed, so it lasts until the end of the input.
+    UnclosedComment,
+    /// A block-level tag in the output which is not balanced.
+    UnbalancedOutput,
+}
+
+/// A problem found by the `auto_p_checked` function.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub kind:    DiagnosticKind,
+    /// The byte range in the input, or in the output for `DiagnosticKind::UnbalancedOutput`.
+    pub span:    Range<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}: {}", self.span.start, self.span.end, self.message)
+    }
+}
+
+/// A problem found by walking through the tags.
+enum Issue<'a> {
+    Unclosed(Range<usize>, &'a str),
+    /// The last field is whether the end tag is of a block-level element.
+    Stray(Range<usize>, &'a str, bool),
+    BlockInInline(Range<usize>, &'a str, &'a str),
+    UnclosedPreserved(Range<usize>, &'a str),
+    UnclosedComment(Range<usize>),
+}
+
+struct OpenElement<'a> {
+    name:         &'a str,
+    span:         Range<usize>,
+    /// The number of the open inline elements when this element is opened.
+    inline_depth: usize,
+}
+
+/// Find the problems of the input `html`.
+pub(crate) fn check_input(engine: &AutoPEngine, html: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    walk(engine, html, |issue| {
+        let (kind, span, message) = match issue {
+            Issue::Unclosed(span, name) => {
+                (DiagnosticKind::UnclosedElement, span, format!("`<{}>` is never closed", name))
+            },
+            Issue::Stray(span, name, _) => (
+                DiagnosticKind::StrayEndTag,
+                span,
+                format!("`</{}>` does not close any open element", name),
+            ),
+            Issue::BlockInInline(span, name, inline_name) => (
+                DiagnosticKind::BlockInInline,
+                span,
+                format!(
+                    "the block-level element `<{}>` is inside the inline element `<{}>`",
+                    name, inline_name
+                ),
+            ),
+            Issue::UnclosedPreserved(span, name) => (
+                DiagnosticKind::UnclosedPreservedElement,
+                span,
+                format!("`<{}>` is never closed, so its inner HTML is not preserved", name),
+            ),
+            Issue::UnclosedComment(span) => (
+                DiagnosticKind::UnclosedComment,
+                span,
+                String::from("the comment is never closed, so it lasts until the end"),
+            ),
+        };
+
+        diagnostics.push(Diagnostic {
+            kind,
+            span,
+            message,
+        });
+    });
+
+    diagnostics.sort_by_key(|e| e.span.start);
+
+    diagnostics
+}
+
+/// Find the unbalanced block-level tags in the output `html`.
+pub(crate) fn check_output(engine: &AutoPEngine, html: &str, diagnostics: &mut Vec<Diagnostic>) {
+    let length = diagnostics.len();
+
+    walk(engine, html, |issue| {
+        let (span, message) = match issue {
+            Issue::Unclosed(span, name) => {
+                (span, format!("`<{}>` is never closed in the output", name))
+            },
+            Issue::Stray(span, name, true) => {
+                (span, format!("`</{}>` does not close any open element in the output", name))
+            },
+            _ => return,
+        };
+
+        diagnostics.push(Diagnostic {
+            kind: DiagnosticKind::UnbalancedOutput,
+            span,
+            message,
+        });
+    });
+
+    diagnostics[length..].sort_by_key(|e| e.span.start);
+}
+
+/// Walk through the tokens of `html` with the stacks of the open block-level and inline elements, and report the issues.
+fn walk<'a, F: FnMut(Issue<'a>)>(engine: &'a AutoPEngine, html: &'a str, mut report: F) {
+    let mut tokenizer = Tokenizer::new(engine, html);
+
+    let mut blocks: Vec<OpenElement> = Vec::new();
+    let mut inlines: Vec<OpenElement> = Vec::new();
+
+    loop {
+        let start = tokenizer.offset();
+
+        let token = match tokenizer.next() {
+            Some(token) => token,
+            None => break,
+        };
+
+        let span = start..tokenizer.offset();
+
+        let tag = match token {
+            Token::Tag(tag) => tag,
+            Token::Comment(comment) if find_comment_end(comment).is_none() => {
+                report(Issue::UnclosedComment(span));
+
+                continue;
+            },
+            _ => continue,
+        };
+
+        if tag.self_closing || is_void_tag_name(tag.name) {
+            continue;
+        }
+
+        let block = engine.is_block_tag_name(tag.name);
+
+        if !tag.end {
+            if engine.is_preserved_tag_name(tag.name) {
+                // a preserved element which has its end tag is a `Token::Preserved`
+                report(Issue::UnclosedPreserved(span, tag.name));
+
+                continue;
+            }
+
+            if block {
+                if let Some(inline) = inlines.last() {
+                    report(Issue::BlockInInline(span.clone(), tag.name, inline.name));
+                }
+
+                blocks.push(OpenElement {
+                    name: tag.name,
+                    span,
+                    inline_depth: inlines.len(),
+                });
+            } else {
+                inlines.push(OpenElement {
+                    name: tag.name,
+                    span,
+                    inline_depth: 0,
+                });
+            }
+
+            continue;
+        }
+
+        if block {
+            match blocks.iter().rposition(|e| e.name.eq_ignore_ascii_case(tag.name)) {
+                Some(index) => {
+                    for element in blocks.drain((index + 1)..) {
+                        if !is_optional_end_tag_name(element.name) {
+                            report(Issue::Unclosed(element.span, element.name));
+                        }
+                    }
+
+                    let element = blocks.pop().unwrap();
+
+                    // the inline elements in the block are closed with it
+                    inlines.truncate(element.inline_depth);
+                },
+                None => report(Issue::Stray(span, tag.name, true)),
+            }
+        } else {
+            match inlines.iter().rposition(|e| e.name.eq_ignore_ascii_case(tag.name)) {
+                Some(index) => inlines.truncate(index),
+                None => report(Issue::Stray(span, tag.name, false)),
+            }
+        }
+    }
+
+    for element in blocks {
+        if !is_optional_end_tag_name(element.name) {
+            report(Issue::Unclosed(element.span, element.name));
+        }
+    }
+}
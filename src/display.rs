@@ -0,0 +1,60 @@
+use std::{
+    borrow::Cow,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::{renderer::Renderer, AutoPEngine, Options};
+
+/// A lazy `auto_p`. The HTML gets `auto_p`ed only when it is being formatted, and the result is written straight into the formatter without an intermediate `String`.
+///
+/// ```rust
+/// use std::fmt::Write;
+///
+/// use html_auto_p::*;
+///
+/// let mut html = String::from("<article>\n");
+///
+/// writeln!(
+///     html,
+///     "{}",
+///     AutoP::new("Paragraph 1\n\nParagraph 2", Options::new())
+/// )
+/// .unwrap();
+///
+/// html.push_str("</article>");
+///
+/// assert_eq!(
+///     "<article>\n<p>Paragraph 1</p>\n<p>Paragraph 2</p>\n</article>",
+///     html
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct AutoP<'a> {
+    pee:    &'a str,
+    engine: Cow<'a, AutoPEngine>,
+}
+
+impl<'a> AutoP<'a> {
+    #[inline]
+    pub const fn new(pee: &'a str, options: Options) -> Self {
+        AutoP {
+            pee,
+            engine: Cow::Owned(AutoPEngine::new(options)),
+        }
+    }
+
+    #[inline]
+    pub(crate) const fn with_engine(pee: &'a str, engine: &'a AutoPEngine) -> Self {
+        AutoP {
+            pee,
+            engine: Cow::Borrowed(engine),
+        }
+    }
+}
+
+impl<'a> Display for AutoP<'a> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Renderer::new(&self.engine, f).render(self.pee)
+    }
+}
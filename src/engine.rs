@@ -0,0 +1,363 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    io::{self, Read, Write},
+};
+
+use crate::{
+    cow_writer::CowWriter,
+    diagnostics::{check_input, check_output},
+    limits::{check_limits, LimitedString},
+    renderer::Renderer,
+    stream::render_stream,
+    tag_names::{is_block_tag_name_except_p, is_custom_element_name, is_preserved_tag_name},
+    AutoP, AutoPError, CustomElementPolicy, Diagnostic, Limits, LinkifyOptions, Options,
+    PreservedTagOptions, SourceMap,
+};
+
+/// An `auto_p` engine with its own sets of block-level elements and preserved elements. Build it once with `AutoPEngine::builder()` and reuse it.
+///
+/// ```rust
+/// use html_auto_p::*;
+///
+/// let engine =
+///     AutoPEngine::builder().block_tags(["callout", "x-card"]).build();
+///
+/// assert_eq!(
+///     "<callout>\n<p>Hello world!</p>\n</callout>",
+///     engine.auto_p("<callout>\n\nHello world!\n\n</callout>")
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AutoPEngine {
+    options:          Options,
+    /// Extra block-level element names in lowercase, sorted.
+    block_tag_names:  Vec<String>,
+    /// Inline element names which override the custom element policy, in lowercase, sorted.
+    inline_tag_names: Vec<String>,
+    /// Registered preserved element names in lowercase with their options, sorted by the names.
+    preserved_tags:   Vec<(String, PreservedTagOptions)>,
+    /// Shortcode names, sorted.
+    shortcode_names:  Vec<String>,
+    linkify:          Option<LinkifyOptions>,
+}
+
+impl AutoPEngine {
+    /// Create an engine which only knows the built-in elements, like the `auto_p` function.
+    #[inline]
+    pub const fn new(options: Options) -> Self {
+        AutoPEngine {
+            options,
+            block_tag_names: Vec::new(),
+            inline_tag_names: Vec::new(),
+            preserved_tags: Vec::new(),
+            shortcode_names: Vec::new(),
+            linkify: None,
+        }
+    }
+
+    /// Create a builder for an engine with its own block-level elements, preserved elements, shortcodes and links.
+    #[inline]
+    pub fn builder() -> AutoPEngineBuilder {
+        AutoPEngineBuilder::default()
+    }
+
+    /// The options which this engine is built with.
+    #[inline]
+    pub const fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Identify text formatted with newlines and replace double line-breaks with HTML paragraph tags. See the `auto_p` function.
+    pub fn auto_p<S: Into<String>>(&self, pee: S) -> String {
+        let mut pee = pee.into();
+
+        let range = match self.auto_p_cow(&pee) {
+            Cow::Owned(pee) => return pee,
+            Cow::Borrowed(s) => {
+                let start = s.as_ptr() as usize - pee.as_ptr() as usize;
+
+                start..(start + s.len())
+            },
+        };
+
+        // nothing changes except the leading and trailing whitespace, so reuse the input
+        pee.truncate(range.end);
+        pee.drain(..range.start);
+
+        pee
+    }
+
+    /// See the `auto_p_cow` function.
+    pub fn auto_p_cow<'a>(&self, pee: &'a str) -> Cow<'a, str> {
+        let mut writer = CowWriter::new(pee);
+
+        Renderer::new(self, &mut writer).render(pee).unwrap();
+
+        writer.into_cow()
+    }
+
+    /// See the `auto_p_to` function.
+    #[inline]
+    pub fn auto_p_to<W: ?Sized + fmt::Write>(&self, pee: &str, out: &mut W) -> fmt::Result {
+        Renderer::new(self, out).render(pee)
+    }
+
+    /// See the `try_auto_p` function.
+    pub fn try_auto_p(&self, pee: &str, limits: Limits) -> Result<String, AutoPError> {
+        check_limits(self, pee, limits)?;
+
+        match limits.max_output_growth {
+            Some(limit) => {
+                let mut out = LimitedString::new(pee.len().saturating_add(limit));
+
+                Renderer::new(self, &mut out).render(pee).map_err(|_| {
+                    AutoPError::OutputTooLong {
+                        limit,
+                    }
+                })?;
+
+                Ok(out.inner)
+            },
+            None => Ok(self.auto_p_cow(pee).into_owned()),
+        }
+    }
+
+    /// See the `auto_p_checked` function.
+    pub fn auto_p_checked(&self, pee: &str) -> (String, Vec<Diagnostic>) {
+        let mut diagnostics = check_input(self, pee);
+
+        let html = self.auto_p_cow(pee).into_owned();
+
+        check_output(self, &html, &mut diagnostics);
+
+        (html, diagnostics)
+    }
+
+    /// See the `auto_p_with_map` function.
+    pub fn auto_p_with_map(&self, pee: &str) -> (String, SourceMap) {
+        let mut html = String::with_capacity(pee.len() + (pee.len() >> 3));
+
+        let source_map = Renderer::new(self, &mut html).render_with_source_map(pee).unwrap();
+
+        (html, source_map)
+    }
+
+    /// See the `auto_p_stream` function.
+    #[inline]
+    pub fn auto_p_stream<R: Read, W: Write>(&self, reader: R, writer: W) -> io::Result<()> {
+        render_stream(self, reader, writer)
+    }
+
+    /// See the `AutoP` struct.
+    #[inline]
+    pub fn display<'a>(&'a self, pee: &'a str) -> AutoP<'a> {
+        AutoP::with_engine(pee, self)
+    }
+
+    /// Whether `name` is a block-level element except `<p>`. The tag name of the paragraph element is a block-level element, so the output does not get wrapped again when it is `auto_p`ed again.
+    #[inline]
+    pub(crate) fn is_block_tag_name_except_p(&self, name: &str) -> bool {
+        is_block_tag_name_except_p(name)
+            || contains(&self.block_tag_names, name)
+            || (self.options.paragraph.valid_tag_name() != "p"
+                && self.options.paragraph.valid_tag_name().eq_ignore_ascii_case(name))
+            || matches!(
+                self.custom_element_policy(name),
+                Some(CustomElementPolicy::Block | CustomElementPolicy::Preserved)
+            )
+    }
+
+    /// Whether `name` is a block-level element, including `<p>`.
+    #[inline]
+    pub(crate) fn is_block_tag_name(&self, name: &str) -> bool {
+        name.eq_ignore_ascii_case("p") || self.is_block_tag_name_except_p(name)
+    }
+
+    /// Whether the inner HTML of the element `name` should not get `auto_p`ed.
+    #[inline]
+    pub(crate) fn is_preserved_tag_name(&self, name: &str) -> bool {
+        is_preserved_tag_name(name)
+            || self.registered_preserved_tag(name).is_some()
+            || self.custom_element_policy(name) == Some(CustomElementPolicy::Preserved)
+    }
+
+    /// How to transform the inner HTML of the preserved element `name`.
+    pub(crate) fn preserved_tag_options(&self, name: &str) -> PreservedTagOptions {
+        match self.registered_preserved_tag(name) {
+            Some(options) => options,
+            None if name.eq_ignore_ascii_case("pre") => PreservedTagOptions {
+                esc:                     self.options.esc_pre,
+                remove_useless_newlines: self.options.remove_useless_newlines_in_pre,
+            },
+            None => PreservedTagOptions::new(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn has_shortcodes(&self) -> bool {
+        !self.shortcode_names.is_empty()
+    }
+
+    /// Whether `name` is a registered shortcode name. Shortcode names are case-sensitive.
+    #[inline]
+    pub(crate) fn is_shortcode_name(&self, name: &str) -> bool {
+        self.shortcode_names.binary_search_by(|e| e.as_str().cmp(name)).is_ok()
+    }
+
+    /// How to turn URLs and e-mail addresses into links, if enabled.
+    #[inline]
+    pub(crate) fn linkify_options(&self) -> Option<&LinkifyOptions> {
+        self.linkify.as_ref()
+    }
+
+    /// The policy for `name` if it is a custom element which has not been registered.
+    #[inline]
+    fn custom_element_policy(&self, name: &str) -> Option<CustomElementPolicy> {
+        if self.options.custom_elements == CustomElementPolicy::Inline
+            || !is_custom_element_name(name)
+            || contains(&self.block_tag_names, name)
+            || contains(&self.inline_tag_names, name)
+            || self.registered_preserved_tag(name).is_some()
+        {
+            return None;
+        }
+
+        Some(self.options.custom_elements)
+    }
+
+    #[inline]
+    fn registered_preserved_tag(&self, name: &str) -> Option<PreservedTagOptions> {
+        if self.preserved_tags.is_empty() {
+            return None;
+        }
+
+        self.preserved_tags
+            .binary_search_by(|(e, _)| cmp_ignore_ascii_case(e, name))
+            .ok()
+            .map(|i| self.preserved_tags[i].1)
+    }
+}
+
+/// Whether a sorted list of lowercase names contains `name` case-insensitively.
+#[inline]
+fn contains(names: &[String], name: &str) -> bool {
+    !names.is_empty() && names.binary_search_by(|e| cmp_ignore_ascii_case(e, name)).is_ok()
+}
+
+/// Compare a lowercase `a` with `b` as if `b` were lowercase.
+#[inline]
+fn cmp_ignore_ascii_case(a: &str, b: &str) -> std::cmp::Ordering {
+    a.bytes().cmp(b.bytes().map(|e| e.to_ascii_lowercase()))
+}
+
+/// A builder of `AutoPEngine`.
+#[derive(Debug, Clone, Default)]
+pub struct AutoPEngineBuilder {
+    options:          Options,
+    block_tag_names:  Vec<String>,
+    inline_tag_names: Vec<String>,
+    preserved_tags:   Vec<(String, PreservedTagOptions)>,
+    shortcode_names:  Vec<String>,
+    linkify:          Option<LinkifyOptions>,
+}
+
+impl AutoPEngineBuilder {
+    #[inline]
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+
+        self
+    }
+
+    /// Add block-level elements, such as custom elements which are used like `<div>`. Tag names are case-insensitive. `p` is always a block-level element and is ignored.
+    #[inline]
+    pub fn block_tags<I: IntoIterator<Item = S>, S: AsRef<str>>(mut self, names: I) -> Self {
+        extend_names(&mut self.block_tag_names, names);
+
+        self
+    }
+
+    /// Add custom elements which should be treated as inline elements regardless of the `custom_elements` option. Tag names are case-insensitive. It cannot turn built-in block-level elements into inline elements.
+    #[inline]
+    pub fn inline_tags<I: IntoIterator<Item = S>, S: AsRef<str>>(mut self, names: I) -> Self {
+        extend_names(&mut self.inline_tag_names, names);
+
+        self
+    }
+
+    /// Add an element whose inner HTML should be kept as it is, such as `<code>`, `<template>` or `<noscript>`, or replace the options of a built-in one (`<pre>`, `<textarea>`, `<script>`, `<style>` and `<svg>`). The tag name is case-insensitive.
+    ///
+    /// Like the built-in ones, a registered element which starts a paragraph is not wrapped in a `<p>` element. Otherwise it is laid out as an inline element, unless it is added by `block_tags`.
+    pub fn preserved_tag<S: AsRef<str>>(mut self, name: S, options: PreservedTagOptions) -> Self {
+        let name = name.as_ref().to_ascii_lowercase();
+
+        if !name.is_empty() {
+            // the last registration wins
+            self.preserved_tags.retain(|(e, _)| *e != name);
+            self.preserved_tags.push((name, options));
+        }
+
+        self
+    }
+
+    /// Add shortcodes, such as `gallery` for `[gallery ids="1,2"]` and `caption` for `[caption]...[/caption]`, like `shortcode_unautop` in Wordpress. Shortcode names are case-sensitive.
+    ///
+    /// A paragraph which is a whole shortcode, and lines which only have shortcodes at the start and the end of a paragraph, do not get wrapped in `<p>` elements. Newlines in the attributes of shortcodes are kept as they are.
+    pub fn shortcodes<I: IntoIterator<Item = S>, S: AsRef<str>>(mut self, names: I) -> Self {
+        self.shortcode_names.extend(
+            names.into_iter().map(|name| name.as_ref().to_string()).filter(|name| !name.is_empty()),
+        );
+
+        self
+    }
+
+    /// Turn URLs starting with `http://`, `https://` or `www.` and e-mail addresses in text into links, like `make_clickable` in Wordpress. Tags, comments, shortcodes, `<a>` elements and preserved elements (such as `<pre>`) are left untouched.
+    #[inline]
+    pub fn linkify(mut self, options: LinkifyOptions) -> Self {
+        self.linkify = Some(options);
+
+        self
+    }
+
+    pub fn build(self) -> AutoPEngine {
+        let mut block_tag_names = self.block_tag_names;
+
+        block_tag_names.retain(|name| !is_block_tag_name_except_p(name));
+        block_tag_names.sort_unstable();
+        block_tag_names.dedup();
+
+        let mut inline_tag_names = self.inline_tag_names;
+
+        inline_tag_names.sort_unstable();
+        inline_tag_names.dedup();
+
+        let mut preserved_tags = self.preserved_tags;
+
+        preserved_tags.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut shortcode_names = self.shortcode_names;
+
+        shortcode_names.sort_unstable();
+        shortcode_names.dedup();
+
+        AutoPEngine {
+            options: self.options,
+            block_tag_names,
+            inline_tag_names,
+            preserved_tags,
+            shortcode_names,
+            linkify: self.linkify,
+        }
+    }
+}
+
+/// Push lowercase `names` except for empty names and `p`.
+fn extend_names<I: IntoIterator<Item = S>, S: AsRef<str>>(target: &mut Vec<String>, names: I) {
+    target.extend(
+        names
+            .into_iter()
+            .map(|name| name.as_ref().to_ascii_lowercase())
+            .filter(|name| !name.is_empty() && name != "p"),
+    );
+}
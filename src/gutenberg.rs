@@ -0,0 +1,304 @@
+use std::borrow::Cow;
+
+use crate::tokenizer::find_comment_end;
+
+/// A block in the HTML serialized by the block editor (Gutenberg), or freeform content between blocks.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Block<'a> {
+    /// The block name, such as `core/paragraph` for `<!-- wp:paragraph -->`. It is `None` for freeform content.
+    pub name:         Option<Cow<'a, str>>,
+    /// The JSON attributes as they are, such as `{"level":2}`.
+    pub attributes:   Option<&'a str>,
+    /// The whole serialized block, including the block comment delimiters.
+    pub raw:          &'a str,
+    /// The blocks nested in this block. Freeform content inside a block is not included.
+    pub inner_blocks: Vec<Block<'a>>,
+}
+
+impl<'a> Block<'a> {
+    /// Whether this is freeform content, which is not in any block.
+    #[inline]
+    pub fn is_freeform(&self) -> bool {
+        self.name.is_none()
+    }
+}
+
+impl<'a> Drop for Block<'a> {
+    /// Drop the nested blocks one after another instead of recursively, so that deeply nested blocks do not overflow the stack.
+    fn drop(&mut self) {
+        let mut blocks = std::mem::take(&mut self.inner_blocks);
+
+        while let Some(mut block) = blocks.pop() {
+            blocks.append(&mut block.inner_blocks);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum DelimiterKind {
+    /// `<!-- wp:name -->`
+    Opener,
+    /// `<!-- /wp:name -->`
+    Closer,
+    /// `<!-- wp:name /-->`
+    Void,
+}
+
+/// A block comment delimiter.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Delimiter<'a> {
+    pub(crate) kind:       DelimiterKind,
+    /// The name after `wp:`, such as `paragraph` and `my-plugin/my-block`.
+    pub(crate) name:       &'a str,
+    pub(crate) attributes: Option<&'a str>,
+}
+
+/// Parse a whole comment as a block comment delimiter, like `<!-- wp:heading {"level":2} -->`.
+pub(crate) fn parse_delimiter(comment: &str) -> Option<Delimiter<'_>> {
+    let content = comment.strip_prefix("<!--")?.strip_suffix("-->")?;
+
+    let trimmed = content.trim_start_matches(is_whitespace);
+
+    if trimmed.len() == content.len() {
+        return None;
+    }
+
+    let (closer, trimmed) = match trimmed.strip_prefix('/') {
+        Some(trimmed) => (true, trimmed),
+        None => (false, trimmed),
+    };
+
+    let trimmed = trimmed.strip_prefix("wp:")?;
+
+    let name_length = trimmed
+        .bytes()
+        .take_while(|e| matches!(e, b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-' | b'/'))
+        .count();
+    let name = &trimmed[..name_length];
+
+    if !is_block_name(name) {
+        return None;
+    }
+
+    let rest = &trimmed[name_length..];
+
+    // the rest is `\s+` or `\s+/` or `\s+{...}\s+` or `\s+{...}\s+/`
+    let rest_trimmed = rest.trim_start_matches(is_whitespace);
+
+    if rest_trimmed.len() == rest.len() && (closer || rest != "/") {
+        return None;
+    }
+
+    let (void, rest_trimmed) = match rest_trimmed.strip_suffix('/') {
+        Some(rest_trimmed) => (true, rest_trimmed),
+        None => (false, rest_trimmed),
+    };
+
+    let attributes = if rest_trimmed.is_empty() {
+        None
+    } else {
+        let attributes = rest_trimmed.trim_end_matches(is_whitespace);
+
+        if attributes.len() == rest_trimmed.len()
+            || !attributes.starts_with('{')
+            || !attributes.ends_with('}')
+        {
+            return None;
+        }
+
+        Some(attributes)
+    };
+
+    let kind = match (closer, void) {
+        (true, false) if attributes.is_none() => DelimiterKind::Closer,
+        (false, false) => DelimiterKind::Opener,
+        (false, true) => DelimiterKind::Void,
+        _ => return None,
+    };
+
+    Some(Delimiter {
+        kind,
+        name,
+        attributes,
+    })
+}
+
+/// Whether `name` is `[a-z][a-z0-9_-]*` with an optional namespace like `namespace/`.
+fn is_block_name(name: &str) -> bool {
+    let is_part = |part: &str| {
+        part.starts_with(|c: char| c.is_ascii_lowercase())
+            && part.bytes().all(|e| matches!(e, b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-'))
+    };
+
+    match name.split_once('/') {
+        Some((namespace, name)) => is_part(namespace) && is_part(name),
+        None => is_part(name),
+    }
+}
+
+#[inline]
+fn is_whitespace(c: char) -> bool {
+    c.is_ascii_whitespace()
+}
+
+/// Find the end of the block whose opener is at the beginning of `s`, and return the length of the whole block including the closer. Nested blocks are skipped.
+pub(crate) fn find_block_end(s: &str, opener_length: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut p = opener_length;
+
+    while let Some(i) = s[p..].find("<!--") {
+        let start = p + i;
+        let length = find_comment_end(&s[start..])?;
+
+        p = start + length;
+
+        match parse_delimiter(&s[start..p]) {
+            Some(Delimiter {
+                kind: DelimiterKind::Opener, ..
+            }) => depth += 1,
+            Some(Delimiter {
+                kind: DelimiterKind::Closer, ..
+            }) => {
+                if depth == 0 {
+                    return Some(p);
+                }
+
+                depth -= 1;
+            },
+            _ => (),
+        }
+    }
+
+    None
+}
+
+/// Parse the HTML serialized by the block editor (Gutenberg) into blocks and freeform content. Whitespace between blocks is not included. An unclosed block lasts until the end of the HTML.
+///
+/// The JSON attributes are not deserialized.
+///
+/// ```rust
+/// use html_auto_p::*;
+///
+/// let blocks = parse_blocks(
+///     "<!-- wp:heading {\"level\":3} -->\n<h3>Title</h3>\n<!-- /wp:heading \
+///      -->\n\nClassic content",
+/// );
+///
+/// assert_eq!(2, blocks.len());
+/// assert_eq!(Some("core/heading"), blocks[0].name.as_deref());
+/// assert_eq!(Some("{\"level\":3}"), blocks[0].attributes);
+/// assert!(blocks[1].is_freeform());
+/// assert_eq!("Classic content", blocks[1].raw);
+/// ```
+pub fn parse_blocks(html: &str) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+
+    // the blocks opened but not closed yet, with their start positions, innermost last
+    let mut open_blocks: Vec<(usize, Delimiter, Vec<Block>)> = Vec::new();
+
+    let mut freeform_start = 0;
+    let mut p = 0;
+
+    while let Some(i) = html[p..].find("<!--") {
+        let start = p + i;
+
+        let length = match find_comment_end(&html[start..]) {
+            Some(length) => length,
+            None => break,
+        };
+
+        p = start + length;
+
+        let delimiter = match parse_delimiter(&html[start..p]) {
+            Some(delimiter) => delimiter,
+            None => continue,
+        };
+
+        let block = match delimiter.kind {
+            DelimiterKind::Opener => {
+                if open_blocks.is_empty() {
+                    push_freeform(&mut blocks, &html[freeform_start..start]);
+                }
+
+                open_blocks.push((start, delimiter, Vec::new()));
+
+                continue;
+            },
+            DelimiterKind::Void => {
+                if open_blocks.is_empty() {
+                    push_freeform(&mut blocks, &html[freeform_start..start]);
+                }
+
+                new_block(delimiter, &html[start..p], Vec::new())
+            },
+            // a closer closes the innermost open block, whatever its name is
+            DelimiterKind::Closer => match open_blocks.pop() {
+                Some((open_start, opener, inner_blocks)) => {
+                    new_block(opener, &html[open_start..p], inner_blocks)
+                },
+                None => continue,
+            },
+        };
+
+        match open_blocks.last_mut() {
+            Some((_, _, inner_blocks)) => inner_blocks.push(block),
+            None => {
+                blocks.push(block);
+
+                freeform_start = p;
+            },
+        }
+    }
+
+    // unclosed blocks last until the end of the HTML
+    while let Some((open_start, opener, inner_blocks)) = open_blocks.pop() {
+        let block = new_block(opener, &html[open_start..], inner_blocks);
+
+        match open_blocks.last_mut() {
+            Some((_, _, inner_blocks)) => inner_blocks.push(block),
+            None => {
+                blocks.push(block);
+
+                freeform_start = html.len();
+            },
+        }
+    }
+
+    push_freeform(&mut blocks, &html[freeform_start..]);
+
+    blocks
+}
+
+#[inline]
+fn new_block<'a>(
+    delimiter: Delimiter<'a>,
+    raw: &'a str,
+    inner_blocks: Vec<Block<'a>>,
+) -> Block<'a> {
+    let name = if delimiter.name.contains('/') {
+        Cow::Borrowed(delimiter.name)
+    } else {
+        Cow::Owned(format!("core/{}", delimiter.name))
+    };
+
+    Block {
+        name: Some(name),
+        attributes: delimiter.attributes,
+        raw,
+        inner_blocks,
+    }
+}
+
+#[inline]
+fn push_freeform<'a>(blocks: &mut Vec<Block<'a>>, html: &'a str) {
+    let html = html.trim_matches(is_whitespace);
+
+    if !html.is_empty() {
+        blocks.push(Block {
+            name:         None,
+            attributes:   None,
+            raw:          html,
+            inner_blocks: Vec::new(),
+        });
+    }
+}
@@ -1,7 +1,7 @@
 /*!
 # HTML auto_p
 
-This library provides a function like `wpautop` in Wordpress. It uses a group of regex replaces used to identify text formatted with newlines and replace double line-breaks with HTML paragraph tags.
+This library provides a function like `wpautop` in Wordpress. It identifies text formatted with newlines and replaces double line-breaks with HTML paragraph tags.
 
 Someone who familiars with HTML would prefer directly writing plain HTML instead of using an editor like TinyMCE or Gutenberg. However, it takes time to manually add newlines and paragraphs in HTML. Wordpress provides a handy function called `wpautop` which can replace double line-breaks with paragraph elements (`<p>`) and convert remaining line-breaks to `<br>` elements.
 
@@ -19,399 +19,379 @@ assert_eq!("<pre>Line 1&lt;br&gt;\nLine 2</pre>", auto_p("<pre>Line 1<br>\nLine
 assert_eq!("<pre>Line 1\nLine 2</pre>", auto_p("<pre>\nLine 1\nLine 2\n</pre>", Options::new().remove_useless_newlines_in_pre(true)));
 ```
 
-## Onig Support (alternative, unstable)
+## Custom Tags
 
-To use the [`onig`](https://crates.io/crates/onig) crate, enable the `onig` feature.
+An `AutoPEngine` can be built with extra block-level elements, such as custom elements, and extra elements whose inner HTML should be kept as it is, such as `<code>`, optionally escaped or trimmed. Build it once and reuse it. The `auto_p` function works like an engine which only knows the built-in elements.
 
-```toml
-[dependencies.html-auto-p]
-version = "*"
-features = ["onig"]
+```rust
+use html_auto_p::*;
+
+let engine = AutoPEngine::builder()
+    .options(Options::new().br(true))
+    .block_tags(["callout"])
+    .preserved_tag("code", PreservedTagOptions::new().esc(true))
+    .build();
+
+assert_eq!("<callout>\n<p>Line 1<br>\nLine 2</p>\n</callout>", engine.auto_p("<callout>\n\nLine 1\nLine 2\n\n</callout>"));
+assert_eq!("<code>a &lt; b\n\nc</code>", engine.auto_p("<code>a < b\n\nc</code>"));
 ```
-*/
 
-#[cfg(feature = "onig")]
-extern crate onig as regex;
+Custom elements, whose names contain hyphens, are inline elements by default. The `custom_elements` option can treat them as block-level elements, optionally with their inner HTML kept as it is.
 
-mod options;
+```rust
+use html_auto_p::*;
 
-#[cfg(not(feature = "onig"))]
-use std::borrow::Cow;
-use std::{fmt::Write, str::from_utf8_unchecked};
+assert_eq!("<x-card>\n<p>Hello world!</p>\n</x-card>", auto_p("<x-card>\n\nHello world!\n\n</x-card>", Options::new().custom_elements(CustomElementPolicy::Block)));
+```
 
-use once_cell::sync::Lazy;
-pub use options::*;
-use regex::Regex;
-use trim_in_place::TrimInPlace;
+Shortcodes can be registered, like `shortcode_unautop` in Wordpress, so that standalone shortcodes do not get wrapped in `<p>` elements.
 
-macro_rules! all_blocks_tag_names_except_p {
-    () => {
-        "table|thead|tfoot|caption|col|colgroup|tbody|tr|td|th|div|dl|dd|dt|ul|ol|li|pre|form|map|area|blockquote|address|math|h[1-6]|hr|fieldset|legend|section|article|aside|hgroup|header|footer|nav|figure|figcaption|details|menu|summary"
-    }
-}
+```rust
+use html_auto_p::*;
 
-macro_rules! all_blocks_tag_names {
-    () => {
-        concat!(all_blocks_tag_names_except_p!(), "|p")
-    };
-}
+let engine = AutoPEngine::builder().shortcodes(["gallery"]).build();
 
-macro_rules! all_preserved_tag_names {
-    () => {
-        "textarea|script|style|svg"
-    };
-}
+assert_eq!("<p>Photos:</p>\n[gallery ids=\"1,2\"]", engine.auto_p("Photos:\n\n[gallery ids=\"1,2\"]"));
+```
 
-macro_rules! all_block_and_preserved_tag_names {
-    () => {
-        concat!(all_blocks_tag_names!(), "|", all_preserved_tag_names!())
-    };
-}
+URLs and e-mail addresses in text can be turned into links, like `make_clickable` in Wordpress. Tags, `<a>` elements and preserved elements are left untouched.
 
-macro_rules! pattern_all_blocks_except_p {
-    () => {
-        concat!("(?i:", all_blocks_tag_names_except_p!(), ")")
-    };
-}
+```rust
+use html_auto_p::*;
 
-macro_rules! pattern_all_blocks {
-    () => {
-        concat!("(?i:", all_blocks_tag_names!(), ")")
-    };
-}
+let engine = AutoPEngine::builder().linkify(LinkifyOptions::new().rel("nofollow")).build();
 
-macro_rules! pattern_all_block_and_preserved_tag_names {
-    () => {
-        concat!("(?i:", all_block_and_preserved_tag_names!(), ")")
-    };
-}
+assert_eq!("<p>See <a href=\"https://example.com\" rel=\"nofollow\">https://example.com</a>.</p>", engine.auto_p("See https://example.com."));
+```
 
-macro_rules! pattern_attributes {
-    () => {
-        "(?:\\s+[^<>\\s=]+(?:=(?:|(?:[^'\"])|(?:[^'\"][^\\s<>]*[^'\"])|(?:\"[^\"]*\")|(?:'[^']*'\
-         )))?)*\\s*"
-    };
-}
+The element which wraps paragraphs can be changed, with attributes and classes for the first, the last and the n-th paragraphs.
 
-static RE_PRE_ELEMENT: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(concat!("(?i)", "(<pre", pattern_attributes!(), r">)([\s\S]*?)(</pre\s*>)")).unwrap()
-});
-static RE_TEXTAREA_ELEMENT: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(concat!(
-        "(?i)",
-        "(<textarea",
-        pattern_attributes!(),
-        r">)([\s\S]*?)(</textarea\s*>)"
-    ))
-    .unwrap()
-});
-static RE_SCRIPT_ELEMENT: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(concat!("(?i)", "(<script", pattern_attributes!(), r">)([\s\S]*?)(</script\s*>)"))
-        .unwrap()
-});
-static RE_STYLE_ELEMENT: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(concat!("(?i)", "(<style", pattern_attributes!(), r">)([\s\S]*?)(</style\s*>)"))
-        .unwrap()
-});
-static RE_SVG_ELEMENT: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(concat!("(?i)", "(<svg", pattern_attributes!(), r">)([\s\S]*?)(</svg\s*>)")).unwrap()
-});
-static RE_BR_ELEMENT: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)<br\s*/?>").unwrap());
-
-static RE_TAG: Lazy<Regex> =
-    Lazy::new(|| Regex::new(concat!(r"</?[^\s<]+(", pattern_attributes!(), r")/?>")).unwrap());
-
-static RE_OTHER_NEWLINE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?:\r\n|\r)").unwrap());
-#[allow(clippy::trivial_regex)]
-static RE_EMPTY_PARAGRAPH: Lazy<Regex> = Lazy::new(|| Regex::new(r"<p></p>").unwrap());
-
-static RE_P_END_TAG_MISSING_START: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(concat!(
-        "(?i)",
-        r"(<",
-        pattern_all_blocks_except_p!(),
-        pattern_attributes!(),
-        r">)(\s*)([^<]+)</p>"
-    ))
-    .unwrap()
-});
-static RE_P_START_TAG_MISSING_END: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(concat!("(?i)", r"<p>([^<]+)(\s*)(</", pattern_all_blocks_except_p!(), r"\s*>)"))
-        .unwrap()
-});
-
-static RE_LI_IN_PARAGRAPH: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(concat!("(?i)", r"<p>(<li", pattern_attributes!(), r">[\s\S]*)</p>")).unwrap()
-});
-
-static RE_BLOCK_AND_PRESERVED_TAG_AFTER_P_START_TAG: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(concat!(
-        "(?i)",
-        r"<p>(</?",
-        pattern_all_block_and_preserved_tag_names!(),
-        pattern_attributes!(),
-        r">)"
-    ))
-    .unwrap()
-});
-static RE_BLOCK_AND_PRESERVED_TAG_BEFORE_P_END_TAG: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(concat!(
-        "(?i)",
-        r"(</?",
-        pattern_all_block_and_preserved_tag_names!(),
-        pattern_attributes!(),
-        r">)</p>"
-    ))
-    .unwrap()
-});
-
-static RE_BR_ELEMENT_AFTER_BLOCK_TAG: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(concat!("(?i)", r"(</?", pattern_all_blocks!(), pattern_attributes!(), r">)<br>\n"))
-        .unwrap()
-});
-static RE_BR_ELEMENT_BEFORE_BLOCK_TAG: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(concat!("(?i)", r"<br>\n(</?", pattern_all_blocks!(), pattern_attributes!(), r">)"))
-        .unwrap()
-});
-
-/// A group of regex replaces used to identify text formatted with newlines and replace double line-breaks with HTML paragraph tags.
-///
-/// The original algorithm can be found in [wp-includes/formatting.php](https://github.com/WordPress/WordPress/blob/101d00601e8d00041218e31194c6f5e0dc4940aa/wp-includes/formatting.php#L442)
-///
-/// This function does not 100% work like `wpautop` does.
-pub fn auto_p<S: Into<String>>(pee: S, options: Options) -> String {
-    let mut pee = pee.into();
+```rust
+use html_auto_p::*;
 
-    pee.trim_in_place();
+let options = Options::new().paragraph(ParagraphElement::new().tag_name("div").attribute("class", "para").first_class("lead"));
 
-    if pee.is_empty() {
-        return pee;
-    }
+assert_eq!("<div class=\"para lead\">Paragraph 1</div>\n<div class=\"para\">Paragraph 2</div>", auto_p("Paragraph 1\n\nParagraph 2", options));
+```
 
-    let mut pre_inner_html_buffer: Vec<(String, usize, usize)> = Vec::new();
-    let mut script_inner_html_buffer: Vec<(String, usize, usize)> = Vec::new();
-    let mut style_inner_html_buffer: Vec<(String, usize, usize)> = Vec::new();
-    let mut textarea_inner_html_buffer: Vec<(String, usize, usize)> = Vec::new();
-    let mut svg_inner_html_buffer: Vec<(String, usize, usize)> = Vec::new();
+## Plain Text
 
-    // The inner HTML in `<pre>`, `<textarea>`, `<script>`, `<style>` and `<svg>` elements should not get `auto_p`ed, so temporarily copy it out, and fill the inner HTML with `'0'`
-    {
-        fn reserve(pee: &mut String, regex: &Regex, buffer: &mut Vec<(String, usize, usize)>) {
-            for captures in regex.captures_iter(pee) {
-                let (s, start, end) = get(&captures, 2);
+With the `plain_text` option, the input is treated as plain text, such as a user comment. Everything is escaped, and only `<p>` and `<br>` elements are added.
 
-                buffer.push((String::from(s), start, end));
-            }
+```rust
+use html_auto_p::*;
 
-            let bytes = unsafe { pee.as_mut_vec() };
+assert_eq!("<p>&lt;b&gt;Tom &amp; Jerry&lt;/b&gt;<br>\n:)</p>", auto_p("<b>Tom & Jerry</b>\n:)", Options::new().br(true).plain_text(true)));
+```
 
-            for (_, start, end) in buffer.iter() {
-                for e in bytes[*start..*end].iter_mut() {
-                    *e = b'0';
-                }
-            }
-        }
+## XHTML
 
-        reserve(&mut pee, &RE_PRE_ELEMENT, &mut pre_inner_html_buffer);
-        reserve(&mut pee, &RE_TEXTAREA_ELEMENT, &mut textarea_inner_html_buffer);
-        reserve(&mut pee, &RE_SCRIPT_ELEMENT, &mut script_inner_html_buffer);
-        reserve(&mut pee, &RE_STYLE_ELEMENT, &mut style_inner_html_buffer);
-        reserve(&mut pee, &RE_SVG_ELEMENT, &mut svg_inner_html_buffer);
-    }
+With the `xhtml` option, the output is well-formed XML which can be embedded in feeds and EPUB files. `<br />` is used, void elements get self-closed, tag names are lowercased, attribute values are double-quoted, `<` and `>` in text are escaped, named character references are converted into numeric ones, control characters are replaced, comments are made valid, and the `<p>` elements are balanced. Other elements in the input should be closed.
 
-    // Standardize newline characters to `"\n"`.
-    let mut pee = replace_all(&RE_OTHER_NEWLINE, pee, "\n");
+```rust
+use html_auto_p::*;
 
-    // Find newlines in all tags and replace them to `'\r'`s.
-    {
-        let mut newlines_in_tags: Vec<usize> = Vec::new();
+assert_eq!("<p>A&#160;B<br />\n<img src=\"1.png\" /></p>", auto_p("A&nbsp;B\n<img src=\"1.png\">", Options::new().br(true).xhtml(true)));
+```
 
-        for captures in RE_TAG.captures_iter(&pee) {
-            let (s, start, _) = get(&captures, 1);
+## Pretty Printing
 
-            for (i, e) in s.bytes().enumerate() {
-                if e == b'\n' {
-                    newlines_in_tags.push(i + start);
-                }
-            }
-        }
+With the `pretty` option, every block-level element and every paragraph starts a line, and nested block-level elements are indented with the given number of spaces. The inner HTML of `<pre>`, `<textarea>`, `<script>`, `<style>` and `<svg>` elements is left untouched.
 
-        let bytes = unsafe { pee.as_mut_vec() };
+```rust
+use html_auto_p::*;
 
-        for newline_index in newlines_in_tags {
-            bytes[newline_index] = b'\r';
-        }
-    }
+assert_eq!("<ul>\n  <li>\n    <p>Item</p>\n  </li>\n</ul>\n<pre>\n keep\n</pre>", auto_p("<ul><li>\n\nItem\n\n</li></ul><pre>\n keep\n</pre>", Options::new().pretty(2)));
+```
 
-    // Split up the contents into an array of strings, separated by at-least-two line breaks.
-    let pees = pee.split("\n\n");
+## Compact Output
 
-    // Reset `pee` prior to rebuilding.
-    let mut pee = String::with_capacity(pee.len());
+With the `compact` option, the newlines between block-level elements and after `<br>` elements are left out, which suits JSON payloads. The newlines around comments and shortcodes on their own lines are kept, so that they stay out of paragraphs when the output is `auto_p`ed again. The inner HTML of preserved elements (such as `<pre>`) is kept as it is.
 
-    // Rebuild the content as a string, wrapping every bit with a `<p>`.
-    for tinkle in pees {
-        pee.write_fmt(format_args!("<p>{}</p>\n", tinkle.trim())).unwrap();
-    }
+```rust
+use html_auto_p::*;
 
-    // Remove empty paragraphs.
-    let mut pee = replace_all(&RE_EMPTY_PARAGRAPH, pee, "");
+assert_eq!("<p>Line 1<br>Line 2</p><pre>\n keep\n</pre><p>End</p>", auto_p("Line 1\nLine 2\n\n<pre>\n keep\n</pre>\n\nEnd", Options::new().br(true).compact(true)));
+```
 
-    pee.trim_matches_in_place('\n');
+## Gutenberg
 
-    // Add a starting `<p>` inside a block element if missing.
-    let pee = replace_all(&RE_P_END_TAG_MISSING_START, pee, "$1$2<p>$3</p>");
+With the `gutenberg` option, only the freeform content in the HTML serialized by the block editor of Wordpress gets `auto_p`ed. The blocks delimited by comments like `<!-- wp:paragraph -->` and `<!-- /wp:paragraph -->` are written as they are. The `parse_blocks` function returns the blocks with their JSON attributes.
 
-    // Add a closing `<p>` inside a block element if missing.
-    let pee = replace_all(&RE_P_START_TAG_MISSING_END, pee, "<p>$1</p>$2$3");
+```rust
+use html_auto_p::*;
 
-    // In some cases `<li>` may get wrapped in `<p>`, fix them.
-    let pee = replace_all(&RE_LI_IN_PARAGRAPH, pee, "$1");
+assert_eq!("<!-- wp:paragraph -->\n<p>Block</p>\n<!-- /wp:paragraph -->\n<p>Classic</p>", auto_p("<!-- wp:paragraph -->\n<p>Block</p>\n<!-- /wp:paragraph -->\n\nClassic", Options::new().gutenberg(true)));
+```
 
-    // If an opening or closing block element tag is preceded by an opening `<p>` tag, remove the `<p>` tag.
-    let pee = replace_all(&RE_BLOCK_AND_PRESERVED_TAG_AFTER_P_START_TAG, pee, "$1");
+## Writing to Other Buffers
 
-    // If an opening or closing block element tag is followed by a closing `</p>` tag, remove the `</p>` tag.
-    let pee = replace_all(&RE_BLOCK_AND_PRESERVED_TAG_BEFORE_P_END_TAG, pee, "$1");
+The `auto_p_to` function writes the result to any `std::fmt::Write`, and the `AutoP` struct implements `Display`, so the result can be formatted straight into a template without an intermediate `String`.
 
-    // Optionally insert line breaks.
-    #[allow(clippy::let_and_return)]
-    let mut pee = if options.br {
-        // Normalize `<br>`
-        let mut pee = replace_all(&RE_BR_ELEMENT, pee, "<br>");
+```rust
+use html_auto_p::*;
 
-        // Replace any new line characters that aren't preceded by a `<br>` with a `<br>`.
-        let mut v = Vec::new();
+let html = format!("<article>{}</article>", AutoP::new("Hello world!", Options::new()));
 
-        {
-            let bytes = pee.as_bytes();
+assert_eq!("<article><p>Hello world!</p></article>", html);
+```
 
-            let mut p = bytes.len();
+## Untrusted Input
 
-            loop {
-                if p == 0 {
-                    break;
-                }
+The `try_auto_p` function fails with an `AutoPError` instead of processing input which exceeds `Limits`, such as the length of the input, the growth of the output, the depth of nested elements and the number of preserved elements.
 
-                p -= 1;
+```rust
+use html_auto_p::*;
 
-                let e = bytes[p];
+let limits = Limits::new().max_input_length(1 << 20).max_output_growth(1 << 16).max_depth(64).max_preserved(256);
 
-                if e == b'\n' {
-                    let mut pp = p;
+assert_eq!(Ok(String::from("<p>Hello world!</p>")), try_auto_p("Hello world!", Options::new(), limits));
+assert_eq!(Err(AutoPError::InputTooLong { length: 12, limit: 8 }), try_auto_p("Hello world!", Options::new(), limits.max_input_length(8)));
+```
 
-                    loop {
-                        if pp == 0 {
-                            break;
-                        }
+## Diagnostics
 
-                        pp -= 1;
+The `auto_p_checked` function also returns the problems which make the output odd, such as stray end tags, unclosed block-level elements, unclosed preserved elements, block-level elements inside inline elements and unbalanced tags in the output, with their byte spans and messages.
 
-                        let e = bytes[pp];
+```rust
+use html_auto_p::*;
 
-                        if !e.is_ascii_whitespace() {
-                            break;
-                        }
-                    }
+let (html, diagnostics) = auto_p_checked("<pre>code\n\nmore", Options::new());
 
-                    if pp < 3 || &bytes[(pp - 3)..=pp] != b"<br>" {
-                        v.push((pp + 1)..p);
-                    }
+assert_eq!("<pre><p>code</p>\n<p>more</p>", html);
+assert_eq!(DiagnosticKind::UnclosedPreservedElement, diagnostics[0].kind);
+assert_eq!(0..5, diagnostics[0].span);
+```
 
-                    p = pp;
-                }
-            }
-        }
+## Source Maps
 
-        for range in v.into_iter() {
-            pee.replace_range(range, "<br>");
-        }
+The `auto_p_with_map` function also returns a `SourceMap`, which maps the byte offsets of every text run and the inner HTML of every preserved element between the output and the input, in both directions.
 
-        // If a `<br>` tag is after an opening or closing block tag, remove it.
-        let pee = replace_all(&RE_BR_ELEMENT_AFTER_BLOCK_TAG, pee, "$1\n");
+```rust
+use html_auto_p::*;
 
-        // If a `<br>` tag is before an opening or closing block tags, remove it.
-        let pee = replace_all(&RE_BR_ELEMENT_BEFORE_BLOCK_TAG, pee, "\n$1");
+let (html, source_map) = auto_p_with_map("Hello\n\n<pre>x</pre>", Options::new());
 
-        pee
-    } else {
-        pee
-    };
+assert_eq!("<p>Hello</p>\n<pre>x</pre>", html);
+assert_eq!(Some(3), source_map.output_offset(0));
+assert_eq!(Some(12), source_map.input_offset(18));
+```
 
-    // Recover the inner HTML that have been filled with `'0'` before.
-    {
-        fn recover(pee: &mut String, regex: &Regex, buffer: &[(String, usize, usize)]) {
-            let mut v = Vec::with_capacity(buffer.len());
+## Streaming
 
-            for (captures, inner_html) in regex.captures_iter(pee).zip(buffer.iter()) {
-                let (_, start, end) = get(&captures, 2);
+The `auto_p_stream` function reads the HTML from a `std::io::Read` and writes the result to a `std::io::Write`. Every paragraph is written out as soon as it ends.
 
-                v.push((start..end, inner_html.0.as_str()));
-            }
+```rust
+use html_auto_p::*;
 
-            for (range, inner_html) in v.into_iter().rev() {
-                pee.replace_range(range, inner_html);
-            }
-        }
+let mut output = Vec::new();
 
-        recover(&mut pee, &RE_SVG_ELEMENT, &svg_inner_html_buffer);
-        recover(&mut pee, &RE_STYLE_ELEMENT, &style_inner_html_buffer);
-        recover(&mut pee, &RE_SCRIPT_ELEMENT, &script_inner_html_buffer);
-        recover(&mut pee, &RE_TEXTAREA_ELEMENT, &svg_inner_html_buffer);
+auto_p_stream("Paragraph 1\n\nParagraph 2".as_bytes(), &mut output, Options::new()).unwrap();
 
-        if options.esc_pre || options.remove_useless_newlines_in_pre {
-            let mut v = Vec::with_capacity(pre_inner_html_buffer.len());
+assert_eq!(b"<p>Paragraph 1</p>\n<p>Paragraph 2</p>", output.as_slice());
+```
 
-            for (captures, inner_html) in
-                RE_PRE_ELEMENT.captures_iter(pee.as_str()).zip(pre_inner_html_buffer.iter())
-            {
-                let (_, start, end) = get(&captures, 2);
+## Removing Paragraphs
 
-                v.push((start..end, inner_html.0.as_str()));
-            }
+The `remove_p` function is the reverse operation of `auto_p`, like `removep` in Wordpress. It turns paragraph tags back into blank lines so that the HTML can be edited as newline-formatted text again.
 
-            if options.esc_pre {
-                if options.remove_useless_newlines_in_pre {
-                    for (range, inner_html) in v.into_iter().rev() {
-                        pee.replace_range(
-                            range,
-                            html_escape::encode_safe(trim_newline_exactly_one(inner_html)).as_ref(),
-                        );
-                    }
-                } else {
-                    for (range, inner_html) in v.into_iter().rev() {
-                        pee.replace_range(range, html_escape::encode_safe(inner_html).as_ref());
-                    }
-                }
-            } else if options.remove_useless_newlines_in_pre {
-                for (range, inner_html) in v.into_iter().rev() {
-                    pee.replace_range(range, trim_newline_exactly_one(inner_html));
-                }
-            } else {
-                for (range, inner_html) in v.into_iter().rev() {
-                    pee.replace_range(range, inner_html);
-                }
-            }
-        } else {
-            recover(&mut pee, &RE_PRE_ELEMENT, &pre_inner_html_buffer);
-        }
-    }
+```rust
+use html_auto_p::*;
 
-    // Recover the newlines in tags that have been replaced with `'\r'` before.
-    {
-        let bytes = unsafe { pee.as_mut_vec() };
+let html = auto_p("Line 1\nLine 2\n\nParagraph 2", Options::new().br(true));
 
-        for e in bytes {
-            if *e == b'\r' {
-                *e = b'\n';
-            }
-        }
-    }
+assert_eq!("Line 1\nLine 2\n\nParagraph 2", remove_p(&html, RemovePOptions::new().br(true)));
+```
+
+## WordPress Compatibility
+
+The `Options::wordpress_compat()` preset reproduces `wpautop` exactly, including its trailing newline and its quirks on malformed HTML, such as putting `</p>` into a `<div>` element which has blank lines. Only the `br` option is used along with it. This mode is useful for migrating content from WordPress byte for byte, but it does not have the guarantees of the other options, such as idempotency.
+
+```rust
+use html_auto_p::*;
+
+assert_eq!("<p>Line 1<br />\nLine 2</p>\n<div>Paragraph 2</div>\n", auto_p("Line 1\nLine 2\n\n<div>Paragraph 2</div>", Options::wordpress_compat()));
+```
+
+## Idempotency
+
+`auto_p` leaves its own output unchanged, so running it again on the output returns the same string, and the fixed point is reached in one step. This holds for well-formed input with every `Options` combination except the following ones, which rewrite what `auto_p` reads:
+
+* `plain_text` and `esc_pre` escape the output again.
+* `preserve_line_endings` may pick another line ending if the input mixes them.
+* `wordpress_compat` keeps the quirks of `wpautop`.
+
+Malformed input, such as stray end tags, and enclosing shortcodes whose content has blank lines may take another run to settle.
+
+```rust
+use html_auto_p::*;
+
+let html = auto_p("Line 1\nLine 2\n\n<div>Paragraph 2</div>", Options::new().br(true));
+
+assert_eq!(html, auto_p(html.as_str(), Options::new().br(true)));
+```
+
+## Implementation
 
-    pee
+Unlike `wpautop`, which runs a group of regex replaces over the whole document, `auto_p` scans the input only once with an HTML-aware tokenizer and writes every paragraph out as soon as it ends, so the cost grows linearly with the size of the input.
+
+There is no backtracking regular expression engine involved, so crafted input, such as long attribute strings and unclosed tags, cannot take more than linear time. The `onig` feature is kept for compatibility, but it no longer has any effect. To reject oversized or deeply nested untrusted input with a recoverable error, use the `try_auto_p` function.
+
+The output is the same as the regex replaces of earlier versions, except on some input, mostly malformed HTML, where those left the `<p>` tags which they added unbalanced. `auto_p` keeps them balanced instead:
+
+* A paragraph in a list item is closed, like `<li><p>a</p>` for `<li>a`.
+* The `</p>` of an empty list item is not left behind, like `<li>\n<li>` for `<li><br>\n\n<li>` with the `br` option.
+* A stray `<p>` or `</p>` next to the content of a paragraph is merged with the added tags, like `<p>a</p>` for `a<p>` and `</p>a`. So a stray tag without any content, such as the whole input `<p>` or `</p>`, is removed like an empty `<p></p>`, and running `auto_p` on the output again does not change it.
+*/
+
+mod cow_writer;
+mod diagnostics;
+mod display;
+mod engine;
+mod gutenberg;
+mod limits;
+mod linkify;
+mod options;
+mod remove_p;
+mod renderer;
+mod source_map;
+mod stream;
+mod tag_names;
+mod tokenizer;
+mod wordpress;
+mod xhtml;
+
+use std::{borrow::Cow, fmt, str::from_utf8_unchecked};
+
+pub use diagnostics::*;
+pub use display::*;
+pub use engine::*;
+pub use gutenberg::*;
+pub use limits::*;
+pub use options::*;
+pub use remove_p::*;
+pub use source_map::*;
+pub use stream::*;
+
+/// Identify text formatted with newlines and replace double line-breaks with HTML paragraph tags.
+///
+/// The original algorithm can be found in [wp-includes/formatting.php](https://github.com/WordPress/WordPress/blob/101d00601e8d00041218e31194c6f5e0dc4940aa/wp-includes/formatting.php#L442).
+///
+/// This function does not 100% work like `wpautop` does. Use `Options::wordpress_compat()` for the exact output of `wpautop`.
+///
+/// Like `wpautop`, it removes the whitespace around `<option>` elements in `<select>` elements, around `<param>` and `<embed>` elements in `<object>` elements, and around `<source>` and `<track>` elements in `<audio>` and `<video>` elements, so that no paragraphs or `<br>` elements are put into them.
+///
+/// Running it again on its output returns the same output. See the crate documentation for the options which do not keep this guarantee.
+#[inline]
+pub fn auto_p<S: Into<String>>(pee: S, options: Options) -> String {
+    AutoPEngine::new(options).auto_p(pee)
 }
 
-fn trim_newline_exactly_one<S: ?Sized + AsRef<str>>(s: &S) -> &str {
+/// Like the `auto_p` function, but borrow the input, and return `Cow::Borrowed` without any allocation if nothing changes (except for the leading and trailing whitespace being trimmed).
+///
+/// ```rust
+/// use std::borrow::Cow;
+///
+/// use html_auto_p::*;
+///
+/// assert_eq!(
+///     Cow::Borrowed("<p>Hello world!</p>"),
+///     auto_p_cow("<p>Hello world!</p>\n", Options::new())
+/// );
+/// assert!(matches!(
+///     auto_p_cow("Hello world!", Options::new()),
+///     Cow::Owned(_)
+/// ));
+/// ```
+#[inline]
+pub fn auto_p_cow(pee: &str, options: Options) -> Cow<'_, str> {
+    AutoPEngine::new(options).auto_p_cow(pee)
+}
+
+/// Like the `auto_p` function, but write the result to `out`, which can be a `String` or a `Formatter`.
+///
+/// To write the result to a `std::io::Write`, use `write!` with [`AutoP`].
+///
+/// ```rust
+/// use html_auto_p::*;
+///
+/// let mut html = String::from("<article>");
+///
+/// auto_p_to("Hello world!", Options::new(), &mut html).unwrap();
+///
+/// html.push_str("</article>");
+///
+/// assert_eq!("<article><p>Hello world!</p></article>", html);
+/// ```
+#[inline]
+pub fn auto_p_to<W: ?Sized + fmt::Write>(pee: &str, options: Options, out: &mut W) -> fmt::Result {
+    AutoPEngine::new(options).auto_p_to(pee, out)
+}
+
+/// Like the `auto_p` function, but fail if the input or the output exceeds `limits`, so that untrusted input can be processed safely.
+///
+/// ```rust
+/// use html_auto_p::*;
+///
+/// let limits = Limits::new().max_input_length(1024).max_depth(2);
+///
+/// assert_eq!(
+///     Ok(String::from("<p>Hello world!</p>")),
+///     try_auto_p("Hello world!", Options::new(), limits)
+/// );
+/// assert_eq!(
+///     Err(AutoPError::TooDeep {
+///         limit: 2
+///     }),
+///     try_auto_p(
+///         "<div><div><b>Hello</b></div></div>",
+///         Options::new(),
+///         limits
+///     )
+/// );
+/// ```
+#[inline]
+pub fn try_auto_p(pee: &str, options: Options, limits: Limits) -> Result<String, AutoPError> {
+    AutoPEngine::new(options).try_auto_p(pee, limits)
+}
+
+/// Like the `auto_p` function, but also return the problems found in `pee` which make the output odd, such as unbalanced block-level tags, preserved elements which are never closed and block-level elements inside inline elements, and the unbalanced block-level tags in the output.
+///
+/// ```rust
+/// use html_auto_p::*;
+///
+/// let (html, diagnostics) = auto_p_checked("Text</div>", Options::new());
+///
+/// assert_eq!("<p>Text</p></div>", html);
+/// assert_eq!(2, diagnostics.len());
+/// assert_eq!(DiagnosticKind::StrayEndTag, diagnostics[0].kind);
+/// assert_eq!(4..10, diagnostics[0].span);
+/// assert_eq!(DiagnosticKind::UnbalancedOutput, diagnostics[1].kind);
+/// assert_eq!(11..17, diagnostics[1].span);
+/// ```
+#[inline]
+pub fn auto_p_checked(pee: &str, options: Options) -> (String, Vec<Diagnostic>) {
+    AutoPEngine::new(options).auto_p_checked(pee)
+}
+
+/// Like the `auto_p` function, but also return a `SourceMap` which maps the byte offsets of the text runs and the inner HTML of preserved elements between the output and `pee`.
+///
+/// ```rust
+/// use html_auto_p::*;
+///
+/// let (html, source_map) = auto_p_with_map("Hello\n\nworld!", Options::new());
+///
+/// assert_eq!("<p>Hello</p>\n<p>world!</p>", html);
+/// assert_eq!(Some(7), source_map.input_offset(16));
+/// assert_eq!(Some(16), source_map.output_offset(7));
+/// assert_eq!(None, source_map.input_offset(0));
+/// ```
+#[inline]
+pub fn auto_p_with_map(pee: &str, options: Options) -> (String, SourceMap) {
+    AutoPEngine::new(options).auto_p_with_map(pee)
+}
+
+pub(crate) fn trim_newline_exactly_one<S: ?Sized + AsRef<str>>(s: &S) -> &str {
     let s = s.as_ref();
     let bytes = s.as_bytes();
     let length = bytes.len();
@@ -486,50 +466,3 @@ fn trim_newline_exactly_one<S: ?Sized + AsRef<str>>(s: &S) -> &str {
 
     unsafe { from_utf8_unchecked(bytes) }
 }
-
-#[cfg(feature = "onig")]
-#[inline]
-fn replace_all(regex: &Regex, pee: String, rep: &str) -> String {
-    regex.replace_all(pee.as_str(), |caps: &regex::Captures| {
-        let mut s = String::with_capacity(rep.len());
-
-        let mut chars = rep.chars();
-
-        while let Some(c) = chars.next() {
-            if c == '$' {
-                let index = (chars.next().unwrap() as u8 - b'0') as usize;
-
-                s.push_str(caps.at(index).unwrap());
-            } else {
-                s.push(c);
-            }
-        }
-
-        s
-    })
-}
-
-#[cfg(not(feature = "onig"))]
-#[inline]
-fn replace_all(regex: &Regex, pee: String, rep: &str) -> String {
-    match regex.replace_all(pee.as_str(), rep) {
-        Cow::Owned(pee) => pee,
-        Cow::Borrowed(_) => pee,
-    }
-}
-
-#[cfg(feature = "onig")]
-#[inline]
-fn get<'a>(captures: &regex::Captures<'a>, index: usize) -> (&'a str, usize, usize) {
-    let (start, end) = captures.pos(index).unwrap();
-
-    (captures.at(index).unwrap(), start, end)
-}
-
-#[cfg(not(feature = "onig"))]
-#[inline]
-fn get<'a>(captures: &regex::Captures<'a>, index: usize) -> (&'a str, usize, usize) {
-    let captures = captures.get(index).unwrap();
-
-    (captures.as_str(), captures.start(), captures.end())
-}
@@ -0,0 +1,235 @@
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter, Write},
+};
+
+use crate::{
+    tag_names::{is_optional_end_tag_name, is_void_tag_name},
+    tokenizer::{Token, Tokenizer},
+    AutoPEngine,
+};
+
+/// Limits for the `try_auto_p` function, for untrusted input. Every limit is disabled by default.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Limits {
+    /// The maximum length of the input in bytes.
+    pub max_input_length:  Option<usize>,
+    /// The maximum number of bytes by which the output may be longer than the input.
+    pub max_output_growth: Option<usize>,
+    /// The maximum depth of nested elements in the input. An element whose end tag is omitted (such as `<li>` and `<p>`) is closed by the start tag which closes it in HTML.
+    pub max_depth:         Option<usize>,
+    /// The maximum number of the elements whose inner HTML is preserved (such as `<pre>`) in the input.
+    pub max_preserved:     Option<usize>,
+}
+
+impl Limits {
+    /// Create limits which are all disabled.
+    #[inline]
+    pub const fn new() -> Self {
+        Limits {
+            max_input_length:  None,
+            max_output_growth: None,
+            max_depth:         None,
+            max_preserved:     None,
+        }
+    }
+
+    /// Set the maximum length of the input in bytes.
+    #[inline]
+    pub const fn max_input_length(mut self, max_input_length: usize) -> Self {
+        self.max_input_length = Some(max_input_length);
+
+        self
+    }
+
+    /// Set the maximum number of bytes by which the output may be longer than the input.
+    #[inline]
+    pub const fn max_output_growth(mut self, max_output_growth: usize) -> Self {
+        self.max_output_growth = Some(max_output_growth);
+
+        self
+    }
+
+    /// Set the maximum depth of nested elements in the input.
+    #[inline]
+    pub const fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+
+        self
+    }
+
+    /// Set the maximum number of the elements whose inner HTML is preserved in the input.
+    #[inline]
+    pub const fn max_preserved(mut self, max_preserved: usize) -> Self {
+        self.max_preserved = Some(max_preserved);
+
+        self
+    }
+}
+
+/// The error of the `try_auto_p` function, which tells which limit has been exceeded.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AutoPError {
+    /// The input is `length` bytes long, which is longer than `limit` bytes.
+    InputTooLong { length: usize, limit: usize },
+    /// The output would be longer than the input by more than `limit` bytes.
+    OutputTooLong { limit: usize },
+    /// Elements are nested more than `limit` levels deep.
+    TooDeep { limit: usize },
+    /// There are more than `limit` elements whose inner HTML is preserved.
+    TooManyPreserved { limit: usize },
+}
+
+impl Display for AutoPError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AutoPError::InputTooLong {
+                length,
+                limit,
+            } => write!(f, "the input is {} bytes long, which exceeds {} bytes", length, limit),
+            AutoPError::OutputTooLong {
+                limit,
+            } => write!(f, "the output grows by more than {} bytes", limit),
+            AutoPError::TooDeep {
+                limit,
+            } => write!(f, "elements are nested more than {} levels deep", limit),
+            AutoPError::TooManyPreserved {
+                limit,
+            } => write!(f, "there are more than {} preserved elements", limit),
+        }
+    }
+}
+
+impl Error for AutoPError {}
+
+/// Check the limits which can be checked before rendering `html`.
+pub(crate) fn check_limits(
+    engine: &AutoPEngine,
+    html: &str,
+    limits: Limits,
+) -> Result<(), AutoPError> {
+    if let Some(limit) = limits.max_input_length {
+        if html.len() > limit {
+            return Err(AutoPError::InputTooLong {
+                length: html.len(),
+                limit,
+            });
+        }
+    }
+
+    if limits.max_depth.is_none() && limits.max_preserved.is_none() {
+        return Ok(());
+    }
+
+    // the names of the open elements
+    let mut open_names: Vec<&str> = Vec::new();
+    let mut preserved = 0;
+
+    for token in Tokenizer::new(engine, html) {
+        match token {
+            Token::Tag(tag) if tag.self_closing || is_void_tag_name(tag.name) => (),
+            Token::Tag(tag) if tag.end => {
+                if let Some(index) =
+                    open_names.iter().rposition(|name| name.eq_ignore_ascii_case(tag.name))
+                {
+                    open_names.truncate(index);
+                }
+            },
+            Token::Tag(tag) => {
+                // elements whose end tags are omitted are closed by the start tags which follow them, such as `<li>` by `<li>`
+                while let Some(name) = open_names.last() {
+                    if is_implicitly_closed_by(engine, name, tag.name) {
+                        open_names.pop();
+                    } else {
+                        break;
+                    }
+                }
+
+                open_names.push(tag.name);
+
+                if let Some(limit) = limits.max_depth {
+                    if open_names.len() > limit {
+                        return Err(AutoPError::TooDeep {
+                            limit,
+                        });
+                    }
+                }
+            },
+            Token::Preserved(_) => {
+                preserved += 1;
+
+                if let Some(limit) = limits.max_preserved {
+                    if preserved > limit {
+                        return Err(AutoPError::TooManyPreserved {
+                            limit,
+                        });
+                    }
+                }
+
+                // a preserved element is one level deeper
+                if let Some(limit) = limits.max_depth {
+                    if open_names.len() + 1 > limit {
+                        return Err(AutoPError::TooDeep {
+                            limit,
+                        });
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the open element `open`, whose end tag may be omitted, is closed by the start tag of `name`.
+fn is_implicitly_closed_by(engine: &AutoPEngine, open: &str, name: &str) -> bool {
+    if !is_optional_end_tag_name(open) {
+        return false;
+    }
+
+    let is = |names: &[&str]| names.iter().any(|e| e.eq_ignore_ascii_case(name));
+
+    match open.to_ascii_lowercase().as_str() {
+        "p" => engine.is_block_tag_name(name),
+        "li" => is(&["li"]),
+        "dt" | "dd" => is(&["dt", "dd"]),
+        "option" => is(&["option", "optgroup"]),
+        "optgroup" => is(&["optgroup"]),
+        "rt" | "rp" => is(&["rt", "rp"]),
+        "tr" => is(&["tr", "thead", "tbody", "tfoot"]),
+        "td" | "th" => is(&["td", "th", "tr", "thead", "tbody", "tfoot"]),
+        "thead" | "tbody" | "tfoot" => is(&["thead", "tbody", "tfoot"]),
+        "colgroup" | "caption" => is(&["caption", "colgroup", "thead", "tbody", "tfoot", "tr"]),
+        _ => false,
+    }
+}
+
+/// A `String` which refuses to grow longer than `limit` bytes.
+pub(crate) struct LimitedString {
+    pub(crate) inner: String,
+    limit:            usize,
+}
+
+impl LimitedString {
+    #[inline]
+    pub(crate) fn new(limit: usize) -> Self {
+        LimitedString {
+            inner: String::new(),
+            limit,
+        }
+    }
+}
+
+impl Write for LimitedString {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.inner.len() + s.len() > self.limit {
+            return Err(fmt::Error);
+        }
+
+        self.inner.push_str(s);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+/// A URL or an e-mail address found in text.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) struct Link {
+    pub(crate) start:       usize,
+    pub(crate) end:         usize,
+    /// What to put before the link text to make the `href` attribute.
+    pub(crate) href_prefix: &'static str,
+}
+
+/// Find the first URL starting with `http://`, `https://` or `www.`, or the first e-mail address in `text`. A link must start at the beginning of `text` or after whitespace, `(`, `[` or `>`.
+pub(crate) fn find_link(text: &str) -> Option<Link> {
+    let bytes = text.as_bytes();
+
+    for start in 0..bytes.len() {
+        if start > 0
+            && !matches!(bytes[start - 1], b'(' | b'[' | b'>')
+            && !bytes[start - 1].is_ascii_whitespace()
+        {
+            continue;
+        }
+
+        let rest = &text[start..];
+
+        let (prefix_length, href_prefix) = if starts_with_ignore_ascii_case(rest, "https://") {
+            (8, "")
+        } else if starts_with_ignore_ascii_case(rest, "http://") {
+            (7, "")
+        } else if starts_with_ignore_ascii_case(rest, "www.") {
+            (4, "http://")
+        } else {
+            match email_length(rest) {
+                Some(length) => {
+                    return Some(Link {
+                        start,
+                        end: start + length,
+                        href_prefix: "mailto:",
+                    })
+                },
+                None => continue,
+            }
+        };
+
+        let length = url_length(rest);
+
+        if length > prefix_length {
+            return Some(Link {
+                start,
+                end: start + length,
+                href_prefix,
+            });
+        }
+    }
+
+    None
+}
+
+#[inline]
+fn starts_with_ignore_ascii_case(s: &str, prefix: &str) -> bool {
+    s.len() >= prefix.len() && s.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+}
+
+/// The length of the URL at the beginning of `s`. Trailing punctuation and unbalanced `)` are not a part of the URL.
+fn url_length(s: &str) -> usize {
+    let mut length = s
+        .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '`'))
+        .unwrap_or(s.len());
+
+    // the parentheses are counted once, and the counts follow the trimming
+    let bytes = &s.as_bytes()[..length];
+    let opening = bytes.iter().filter(|e| **e == b'(').count();
+    let mut closing = bytes.iter().filter(|e| **e == b')').count();
+
+    loop {
+        match bytes[..length].last() {
+            Some(b'.' | b',' | b';' | b':' | b'!' | b'?' | b'\'') => length -= 1,
+            Some(b')') if opening < closing => {
+                length -= 1;
+                closing -= 1;
+            },
+            _ => return length,
+        }
+    }
+}
+
+/// The length of the e-mail address at the beginning of `s`, like `name@example.com`.
+fn email_length(s: &str) -> Option<usize> {
+    let bytes = s.as_bytes();
+
+    let local_length = bytes
+        .iter()
+        .take_while(|e| e.is_ascii_alphanumeric() || matches!(e, b'.' | b'_' | b'%' | b'+' | b'-'))
+        .count();
+
+    if local_length == 0 || bytes.get(local_length) != Some(&b'@') {
+        return None;
+    }
+
+    let domain = &s[(local_length + 1)..];
+
+    let domain_length = domain
+        .bytes()
+        .take_while(|e| e.is_ascii_alphanumeric() || matches!(e, b'.' | b'-'))
+        .count();
+    let domain = domain[..domain_length].trim_end_matches('.');
+
+    let valid = domain.contains('.')
+        && domain.split('.').all(|label| !label.is_empty())
+        && domain
+            .rsplit('.')
+            .next()
+            .map(|tld| tld.len() >= 2 && tld.bytes().all(|e| e.is_ascii_alphabetic()))
+            .unwrap_or(false);
+
+    if valid {
+        Some(local_length + 1 + domain.len())
+    } else {
+        None
+    }
+}
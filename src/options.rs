@@ -1,3 +1,10 @@
+use std::borrow::Cow;
+
+use crate::tag_names::{
+    is_block_tag_name_except_p, is_custom_element_name, is_preserved_tag_name,
+    is_valid_attribute_name, is_valid_tag_name, is_void_tag_name,
+};
+
 #[derive(Default, Debug, Clone)]
 /// Options for the `auto_p` function.
 pub struct Options {
@@ -7,6 +14,24 @@ pub struct Options {
     pub esc_pre:                        bool,
     /// Whether to remove useless newlines in the inner HTML of `<pre>` elements. This is useful to beautifully form code into `<pre>\n...\n</pre>` without worrying about the adjacent newlines' effects.
     pub remove_useless_newlines_in_pre: bool,
+    /// How to treat custom elements, whose names contain hyphens, such as `<my-gallery>`.
+    pub custom_elements:                CustomElementPolicy,
+    /// Whether to only `auto_p` the freeform content in the HTML serialized by the block editor (Gutenberg). The blocks delimited by comments like `<!-- wp:paragraph -->` and `<!-- /wp:paragraph -->` are written as they are, and separated from other content by newlines like paragraphs.
+    pub gutenberg:                      bool,
+    /// Whether the input is plain text instead of HTML. Everything is escaped, and only `<p>` and `<br>` elements are added.
+    pub plain_text:                     bool,
+    /// Whether to make the output well-formed XML, such as XHTML in feeds. `<br />` is used instead of `<br>`, void elements (also in `<pre>`) get self-closed, tag names are lowercased, attribute values are double-quoted, `<` and `>` in text are escaped, named character references except the five predefined ones in XML are converted into numeric ones, the characters which are not allowed in XML (such as control characters and `&#0;`) are replaced with U+FFFD, `--` in comments is split by a space, and the `<p>` elements are balanced. Other elements in the input should be closed, and the inner HTML of `<script>`, `<style>` and `<svg>` elements is not checked.
+    pub xhtml:                          bool,
+    /// The element which wraps paragraphs instead of `<p>`.
+    pub paragraph:                      ParagraphElement,
+    /// The number of spaces to indent nested block-level elements with, if the output should be pretty-printed. Every block-level element and every paragraph starts a line, and the inner HTML of preserved elements (such as `<pre>`) is kept as it is. Nesting deeper than 32 levels is indented like the 32nd level.
+    pub pretty:                         Option<usize>,
+    /// Whether to leave out the newlines between block-level elements and after `<br>` elements, and other whitespace which does not affect the rendering, such as for JSON payloads. The newlines around comments and shortcodes on their own lines are kept. The inner HTML of preserved elements (such as `<pre>`) is kept as it is. It overrides the `pretty` option.
+    pub compact:                        bool,
+    /// Whether to write newlines with the dominant line ending of the input (`\n`, `\r\n` or `\r`) instead of `\n`. The inner HTML of preserved elements (such as `<pre>`), comments and shortcodes are always kept as they are.
+    pub preserve_line_endings:          bool,
+    /// Whether to reproduce `wpautop` in WordPress exactly instead, including its quirks on malformed HTML, such as the `<p>` tags it leaves unbalanced. Only the `br` option is used along with it, like the `$br` argument of `wpautop`. The other options, and the elements, shortcodes and links registered in an `AutoPEngine`, are ignored.
+    pub wordpress_compat:               bool,
 }
 
 impl Options {
@@ -17,9 +42,28 @@ impl Options {
             br:                             false,
             esc_pre:                        false,
             remove_useless_newlines_in_pre: false,
+            custom_elements:                CustomElementPolicy::Inline,
+            gutenberg:                      false,
+            plain_text:                     false,
+            xhtml:                          false,
+            paragraph:                      ParagraphElement::new(),
+            pretty:                         None,
+            compact:                        false,
+            preserve_line_endings:          false,
+            wordpress_compat:               false,
         }
     }
 
+    /// Create options which reproduce `wpautop` in WordPress exactly, with `br` enabled like the default of `wpautop`. See the `wordpress_compat` field.
+    #[inline]
+    pub const fn wordpress_compat() -> Self {
+        let mut options = Self::new().br(true);
+
+        options.wordpress_compat = true;
+
+        options
+    }
+
     /// Set whether to convert remaining line-breaks to `<br>` elements.
     #[inline]
     pub const fn br(mut self, br: bool) -> Self {
@@ -46,4 +90,259 @@ impl Options {
 
         self
     }
+
+    /// Set how to treat custom elements, whose names contain hyphens.
+    #[inline]
+    pub const fn custom_elements(mut self, custom_elements: CustomElementPolicy) -> Self {
+        self.custom_elements = custom_elements;
+
+        self
+    }
+
+    /// Set whether to only `auto_p` the freeform content in the HTML serialized by the block editor (Gutenberg).
+    #[inline]
+    pub const fn gutenberg(mut self, gutenberg: bool) -> Self {
+        self.gutenberg = gutenberg;
+
+        self
+    }
+
+    /// Set whether the input is plain text instead of HTML.
+    #[inline]
+    pub const fn plain_text(mut self, plain_text: bool) -> Self {
+        self.plain_text = plain_text;
+
+        self
+    }
+
+    /// Set whether to make the output well-formed XML.
+    #[inline]
+    pub const fn xhtml(mut self, xhtml: bool) -> Self {
+        self.xhtml = xhtml;
+
+        self
+    }
+
+    /// Pretty-print the output, indenting nested block-level elements with `indent` spaces.
+    #[inline]
+    pub const fn pretty(mut self, indent: usize) -> Self {
+        self.pretty = Some(indent);
+
+        self
+    }
+
+    /// Set whether to leave out the newlines between block-level elements and after `<br>` elements.
+    #[inline]
+    pub const fn compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+
+        self
+    }
+
+    /// Set whether to write newlines with the dominant line ending of the input instead of `\n`.
+    #[inline]
+    pub const fn preserve_line_endings(mut self, preserve_line_endings: bool) -> Self {
+        self.preserve_line_endings = preserve_line_endings;
+
+        self
+    }
+
+    /// Set the element which wraps paragraphs instead of `<p>`.
+    #[inline]
+    pub fn paragraph(mut self, paragraph: ParagraphElement) -> Self {
+        self.paragraph = paragraph;
+
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+/// How to treat custom elements, whose names start with an ASCII letter and contain hyphens, such as `<my-gallery>` and `<x-alert>`. A tag name added by `AutoPEngineBuilder::block_tags`, `AutoPEngineBuilder::inline_tags` or `AutoPEngineBuilder::preserved_tag` overrides the policy.
+pub enum CustomElementPolicy {
+    /// Like `<span>`. Custom elements get wrapped in `<p>` elements. This is the default policy.
+    Inline,
+    /// Like `<div>`. Custom elements on their own lines do not get wrapped in `<p>` elements, but their inner HTML gets `auto_p`ed.
+    Block,
+    /// Like `<div>`, but the inner HTML of custom elements is kept as it is.
+    Preserved,
+}
+
+impl Default for CustomElementPolicy {
+    #[inline]
+    fn default() -> Self {
+        CustomElementPolicy::Inline
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+/// Options for an element whose inner HTML is preserved, such as `<pre>`. See `AutoPEngineBuilder::preserved_tag`.
+pub struct PreservedTagOptions {
+    /// Whether to escape the inner HTML.
+    pub esc:                     bool,
+    /// Whether to remove useless newlines in the inner HTML, like the `remove_useless_newlines_in_pre` option.
+    pub remove_useless_newlines: bool,
+}
+
+impl PreservedTagOptions {
+    /// Create default options. (All false)
+    #[inline]
+    pub const fn new() -> Self {
+        PreservedTagOptions {
+            esc: false, remove_useless_newlines: false
+        }
+    }
+
+    /// Set whether to escape the inner HTML.
+    #[inline]
+    pub const fn esc(mut self, esc: bool) -> Self {
+        self.esc = esc;
+
+        self
+    }
+
+    /// Set whether to remove useless newlines in the inner HTML.
+    #[inline]
+    pub const fn remove_useless_newlines(mut self, remove_useless_newlines: bool) -> Self {
+        self.remove_useless_newlines = remove_useless_newlines;
+
+        self
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+/// Options for turning URLs and e-mail addresses in text into links, like `make_clickable` in Wordpress. See `AutoPEngineBuilder::linkify`.
+pub struct LinkifyOptions {
+    /// The `rel` attribute of the links to URLs, such as `nofollow`.
+    pub rel:    Option<String>,
+    /// The `target` attribute of the links to URLs, such as `_blank`.
+    pub target: Option<String>,
+}
+
+impl LinkifyOptions {
+    /// Create default options. (No `rel` and `target` attributes)
+    #[inline]
+    pub const fn new() -> Self {
+        LinkifyOptions {
+            rel: None, target: None
+        }
+    }
+
+    /// Set the `rel` attribute of the links to URLs.
+    #[inline]
+    pub fn rel<S: Into<String>>(mut self, rel: S) -> Self {
+        self.rel = Some(rel.into());
+
+        self
+    }
+
+    /// Set the `target` attribute of the links to URLs.
+    #[inline]
+    pub fn target<S: Into<String>>(mut self, target: S) -> Self {
+        self.target = Some(target.into());
+
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The element which wraps paragraphs, such as `<p class="lead">` for the first paragraph, `<p style="margin: 0">` for HTML e-mails and `<div class="para">` for widgets. Only the paragraphs added by `auto_p` use it, and the `<p>` elements in the input are kept as they are.
+pub struct ParagraphElement {
+    /// The tag name of a block-level element, or of a custom element, which is treated as a block-level element. Any other tag name, and an invalid tag name, is replaced with `p` when it is written. (Default: `p`)
+    pub tag_name:    Cow<'static, str>,
+    /// The attributes of every paragraph, as pairs of names and unescaped values. An attribute whose name is invalid is left out when it is written.
+    pub attributes:  Vec<(String, String)>,
+    /// The class of the first paragraph.
+    pub first_class: Option<String>,
+    /// The class of the last paragraph. The last paragraph is not written until the end of the input, so `auto_p_stream` buffers one more paragraph.
+    pub last_class:  Option<String>,
+    /// The class of the n-th paragraph, counting from 1.
+    pub nth_class:   Option<(usize, String)>,
+}
+
+impl Default for ParagraphElement {
+    #[inline]
+    fn default() -> Self {
+        ParagraphElement::new()
+    }
+}
+
+impl ParagraphElement {
+    /// Create a plain `<p>` element.
+    #[inline]
+    pub const fn new() -> Self {
+        ParagraphElement {
+            tag_name:    Cow::Borrowed("p"),
+            attributes:  Vec::new(),
+            first_class: None,
+            last_class:  None,
+            nth_class:   None,
+        }
+    }
+
+    /// Set the tag name, such as `div` and `my-para`. The name of an inline element like `span`, and an invalid tag name like `p onclick=x`, are replaced with `p` when they are written.
+    #[inline]
+    pub fn tag_name<S: Into<String>>(mut self, tag_name: S) -> Self {
+        self.tag_name = Cow::Owned(tag_name.into());
+
+        self
+    }
+
+    /// Add an attribute to every paragraph. The value is escaped when it is written. A `class` attribute is merged with the classes of positions. An attribute whose name is invalid, such as `x" onclick="y`, is left out when it is written.
+    #[inline]
+    pub fn attribute<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.attributes.push((name.into(), value.into()));
+
+        self
+    }
+
+    /// Set the class of the first paragraph.
+    #[inline]
+    pub fn first_class<S: Into<String>>(mut self, class: S) -> Self {
+        self.first_class = Some(class.into());
+
+        self
+    }
+
+    /// Set the class of the last paragraph.
+    #[inline]
+    pub fn last_class<S: Into<String>>(mut self, class: S) -> Self {
+        self.last_class = Some(class.into());
+
+        self
+    }
+
+    /// Set the class of the n-th paragraph, counting from 1.
+    #[inline]
+    pub fn nth_class<S: Into<String>>(mut self, n: usize, class: S) -> Self {
+        self.nth_class = Some((n, class.into()));
+
+        self
+    }
+
+    /// The tag name, or `p` if it is invalid or not the name of a block-level element. Void elements and the elements whose inner HTML is preserved, such as `<hr>` and `<pre>`, cannot hold paragraphs either. Custom elements are treated as block-level elements.
+    #[inline]
+    pub(crate) fn valid_tag_name(&self) -> &str {
+        let name = self.tag_name.as_ref();
+
+        let block = is_custom_element_name(name)
+            || (is_block_tag_name_except_p(name)
+                && !is_void_tag_name(name)
+                && !is_preserved_tag_name(name));
+
+        if block && is_valid_tag_name(name) {
+            &self.tag_name
+        } else {
+            "p"
+        }
+    }
+
+    /// Whether this is a plain `<p>` element.
+    #[inline]
+    pub(crate) fn is_plain_p(&self) -> bool {
+        self.valid_tag_name() == "p"
+            && !self.attributes.iter().any(|(name, _)| is_valid_attribute_name(name))
+            && self.first_class.is_none()
+            && self.last_class.is_none()
+            && self.nth_class.is_none()
+    }
 }
@@ -0,0 +1,194 @@
+use std::iter::Peekable;
+
+use crate::{
+    tokenizer::{Token, Tokenizer},
+    AutoPEngine, Options,
+};
+
+#[derive(Default, Debug, Clone)]
+/// Options for the `remove_p` function.
+pub struct RemovePOptions {
+    /// Whether to convert `<br>` elements followed by newlines back to newlines. This should be the same as the `br` option used with the `auto_p` function.
+    pub br: bool,
+}
+
+impl RemovePOptions {
+    /// Create default options. (All false)
+    #[inline]
+    pub const fn new() -> Self {
+        RemovePOptions {
+            br: false
+        }
+    }
+
+    /// Set whether to convert `<br>` elements followed by newlines back to newlines.
+    #[inline]
+    pub const fn br(mut self, br: bool) -> Self {
+        self.br = br;
+
+        self
+    }
+}
+
+/// The reverse operation of the `auto_p` function, like `removep` in Wordpress. It replaces paragraph tags with double line-breaks so that the HTML can be edited as newline-formatted text again.
+///
+/// Only the `<p>` tags written as `<p>` and the `</p>` tags which close them are removed, in pairs. A pair is removed only if the `<p>` tag starts a line, and the `</p>` tag ends a line or is followed by the end tag of a block-level element. The inner HTML of `<pre>`, `<textarea>`, `<script>`, `<style>` and `<svg>` elements is left untouched.
+///
+/// If `html` is produced by the `auto_p` function without the `esc_pre` option from text whose block-level elements are separated from paragraphs by blank lines, `auto_p(remove_p(html, remove_p_options), options)` is the same as `html` when `remove_p_options.br` is `options.br`.
+///
+/// ```rust
+/// use html_auto_p::*;
+///
+/// assert_eq!(
+///     "Paragraph 1\n\nParagraph 2",
+///     remove_p(
+///         "<p>Paragraph 1</p>\n<p>Paragraph 2</p>",
+///         RemovePOptions::new()
+///     )
+/// );
+/// assert_eq!(
+///     "Line 1\nLine 2",
+///     remove_p("<p>Line 1<br>\nLine 2</p>", RemovePOptions::new().br(true))
+/// );
+/// ```
+pub fn remove_p(pee: &str, options: RemovePOptions) -> String {
+    let mut result = String::with_capacity(pee.len());
+
+    let engine = AutoPEngine::new(Options::new());
+
+    let mut tokens = Tokenizer::new(&engine, pee).peekable();
+
+    // whitespace which has not been written yet
+    let mut whitespace = String::new();
+    let mut newlines = 0;
+    // whether the whitespace is at a paragraph boundary
+    let mut boundary = false;
+    // whether the last `<p>` has been removed
+    let mut p_removed = false;
+
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Newline => {
+                whitespace.push('\n');
+                newlines += 1;
+
+                continue;
+            },
+            Token::Text(text) if text.trim().is_empty() => {
+                whitespace.push_str(text);
+
+                continue;
+            },
+            Token::Tag(tag) if tag.raw == "<p>" => {
+                // paragraphs are separated by newlines only
+                if (result.is_empty() || (newlines > 0 && newlines == whitespace.len()))
+                    && is_end_removable(&engine, tokens.clone())
+                {
+                    boundary = true;
+                    p_removed = true;
+
+                    continue;
+                }
+
+                p_removed = false;
+            },
+            Token::Tag(tag) if tag.raw == "</p>" && p_removed => {
+                boundary = true;
+                p_removed = false;
+
+                continue;
+            },
+            Token::Tag(tag) if options.br && tag.is_br() && next_is_newline(&mut tokens) => {
+                continue;
+            },
+            Token::Tag(tag) if !tag.end && tag.is("p") => p_removed = false,
+            _ => (),
+        }
+
+        if !result.is_empty() {
+            if boundary {
+                // one more newline for every newline, so that the newlines between paragraphs become blank lines
+                push_newlines(&mut result, newlines * 2);
+            } else {
+                push_whitespace(&mut result, &whitespace);
+            }
+        }
+
+        whitespace.clear();
+        newlines = 0;
+        boundary = false;
+
+        match token {
+            Token::Text(text) => result.push_str(text),
+            Token::Tag(tag) => result.push_str(tag.raw),
+            Token::Comment(comment) | Token::Block(comment) => result.push_str(comment),
+            Token::Shortcode(shortcode) => result.push_str(shortcode.raw),
+            Token::Preserved(preserved) => {
+                result.push_str(preserved.start_tag.raw);
+                result.push_str(preserved.inner_html);
+                result.push_str(preserved.end_tag);
+            },
+            Token::Newline => unreachable!(),
+        }
+    }
+
+    result
+}
+
+/// Whether the `</p>` which closes the `<p>` before `tokens` can be removed, because it is followed by a newline, the end or the end tag of a block-level element. A `<p>` element without its `</p>` can always be removed.
+fn is_end_removable<'a, I: Iterator<Item = Token<'a>>>(
+    engine: &AutoPEngine,
+    mut tokens: I,
+) -> bool {
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Tag(tag) if tag.is("p") => {
+                if !tag.end {
+                    return true;
+                }
+
+                return tag.raw == "</p>"
+                    && match tokens.next() {
+                        None | Some(Token::Newline) => true,
+                        Some(Token::Tag(tag)) => tag.end && engine.is_block_tag_name(tag.name),
+                        _ => false,
+                    };
+            },
+            _ => (),
+        }
+    }
+
+    true
+}
+
+#[inline]
+fn next_is_newline<'a, I: Iterator<Item = Token<'a>>>(tokens: &mut Peekable<I>) -> bool {
+    matches!(tokens.peek(), Some(Token::Newline))
+}
+
+#[inline]
+fn push_newlines(result: &mut String, count: usize) {
+    for _ in 0..count {
+        result.push('\n');
+    }
+}
+
+/// Push `whitespace` with every run of consecutive newlines doubled except for single newlines, because consecutive newlines come from empty paragraphs.
+fn push_whitespace(result: &mut String, whitespace: &str) {
+    let mut newlines = 0;
+
+    for c in whitespace.chars() {
+        if c == '\n' {
+            newlines += 1;
+
+            continue;
+        }
+
+        push_newlines(result, if newlines > 1 { newlines * 2 } else { newlines });
+        newlines = 0;
+
+        result.push(c);
+    }
+
+    push_newlines(result, if newlines > 1 { newlines * 2 } else { newlines });
+}
@@ -0,0 +1,1664 @@
+use std::fmt::{self, Write};
+
+use crate::{
+    linkify::find_link,
+    source_map::{SourceMap, SourceMapBuilder},
+    tag_names::{is_valid_attribute_name, is_void_tag_name},
+    tokenizer::{Preserved, Shortcode, Tag, Token, Tokenizer},
+    trim_newline_exactly_one,
+    wordpress::wpautop,
+    xhtml::{
+        write_with_numeric_references, write_xhtml_comment, write_xhtml_inner_html,
+        write_xhtml_tag, write_xhtml_text,
+    },
+    AutoPEngine, Options, ParagraphElement, PreservedTagOptions,
+};
+
+/// What the last written non-whitespace content is.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Last {
+    Nothing,
+    Block,
+    /// Content after which no `<br>` is added, such as a comment which starts a line.
+    Standalone,
+    /// A `<br>` element which is written as it is.
+    Br,
+    Other,
+}
+
+/// Where a `<br>` element which has not been written yet is.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum PendingBr {
+    /// Right after a block tag.
+    AfterBlock,
+    /// Right after another `<br>` element. It is never removed, otherwise the `<br>` element before it could be removed when the output is `auto_p`ed again.
+    AfterBr,
+    /// At the start of a line. It is never removed, otherwise the newlines around it would become a blank line.
+    LineStart,
+    /// After whitespace in the compact mode or the pretty mode. It is never removed, otherwise the whitespace before it would be removed or replaced when the output is `auto_p`ed again.
+    AfterSpace,
+    Other,
+}
+
+/// Tracks where whitespace should be removed before paragraphs are made, like `wpautop` in Wordpress does in `<select>`, `<object>`, `<audio>` and `<video>` elements.
+#[derive(Debug, Clone, Copy, Default)]
+struct Collapse {
+    select_depth: usize,
+    object_depth: usize,
+    media_depth:  usize,
+    /// Whether the whitespace after the last token should be removed.
+    after:        bool,
+}
+
+impl Collapse {
+    /// Whether the whitespace before the next token may be removed.
+    #[inline]
+    fn is_inside(&self) -> bool {
+        self.select_depth > 0 || self.object_depth > 0 || self.media_depth > 0
+    }
+
+    /// Whether the whitespace before `token` should be removed.
+    fn before(&self, token: &Token) -> bool {
+        let tag = match token {
+            Token::Tag(tag) => tag,
+            _ => return false,
+        };
+
+        (self.select_depth > 0
+            && ((!tag.end && (tag.is("option") || tag.is("optgroup")))
+                || (tag.end && tag.is("select"))))
+            || (self.object_depth > 0
+                && ((tag.end && tag.is("object")) || tag.is("param") || tag.is("embed")))
+            || (self.media_depth > 0
+                && ((tag.end && (tag.is("audio") || tag.is("video")))
+                    || tag.is("source")
+                    || tag.is("track")))
+    }
+
+    fn update(&mut self, token: &Token) {
+        let tag = match token {
+            Token::Tag(tag) => tag,
+            _ => {
+                self.after = false;
+
+                return;
+            },
+        };
+
+        self.after = (!tag.end
+            && (tag.is("select") || tag.is("object") || tag.is("audio") || tag.is("video")))
+            || (self.select_depth > 0 && tag.end && (tag.is("option") || tag.is("optgroup")))
+            || (self.object_depth > 0 && (tag.is("param") || tag.is("embed")))
+            || (self.media_depth > 0 && (tag.is("source") || tag.is("track")));
+
+        if tag.is("select") {
+            self.select_depth = depth(self.select_depth, tag);
+        } else if tag.is("object") {
+            self.object_depth = depth(self.object_depth, tag);
+        } else if tag.is("audio") || tag.is("video") {
+            self.media_depth = depth(self.media_depth, tag);
+        }
+
+        fn depth(depth: usize, tag: &Tag) -> usize {
+            if tag.self_closing {
+                depth
+            } else if tag.end {
+                depth.saturating_sub(1)
+            } else {
+                depth + 1
+            }
+        }
+    }
+}
+
+/// The deepest nesting level which is indented in the pretty mode. Deeper elements are indented like this level, so that the indentation of deeply nested input does not grow the output quadratically.
+const MAX_INDENT_DEPTH: usize = 32;
+
+/// The output, which can hold back the last paragraph whose start tag depends on whether it is the last one.
+struct Output<W: Write> {
+    inner:       W,
+    /// The position of the held paragraph, and everything written after its start tag.
+    held:        Option<(usize, String)>,
+    /// What `\n` is written as.
+    line_ending: &'static str,
+    /// The number of the bytes written, including the held paragraph.
+    written:     usize,
+}
+
+impl<W: Write> Output<W> {
+    /// Write `s` without converting its newlines.
+    #[inline]
+    fn write_raw(&mut self, s: &str) -> fmt::Result {
+        self.written += s.len();
+
+        match &mut self.held {
+            Some((_, content)) => {
+                content.push_str(s);
+
+                Ok(())
+            },
+            None => self.inner.write_str(s),
+        }
+    }
+}
+
+impl<W: Write> Write for Output<W> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.line_ending == "\n" {
+            return self.write_raw(s);
+        }
+
+        let mut lines = s.split('\n');
+
+        if let Some(line) = lines.next() {
+            self.write_raw(line)?;
+        }
+
+        for line in lines {
+            self.write_raw(self.line_ending)?;
+            self.write_raw(line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes to an `Output` without converting newlines.
+struct Raw<'a, W: Write>(&'a mut Output<W>);
+
+impl<'a, W: Write> Write for Raw<'a, W> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_raw(s)
+    }
+}
+
+/// Turns tokens into paragraphs and writes them out.
+///
+/// The input is consumed paragraph by paragraph. A paragraph is a group of tokens separated by two adjacent newlines. Only the tokens of the current paragraph and a little whitespace are buffered.
+pub(crate) struct Renderer<'e, W: Write> {
+    engine:          &'e AutoPEngine,
+    out:             Output<W>,
+    has_output:      bool,
+    /// Whitespace which has not been written yet, because it may be replaced with `<br>`.
+    whitespace:      String,
+    /// A `<br>` element which has not been written yet, because it may be removed.
+    br:              Option<PendingBr>,
+    last:            Last,
+    /// Whether the last rendered thing is a block of the block editor, so the newlines after it do not make empty paragraphs.
+    after_block:     bool,
+    /// The state of whitespace removal where the unrendered input starts.
+    collapse:        Collapse,
+    /// The number of the unclosed `<a>` elements, in which no links are added.
+    link_depth:      usize,
+    /// The number of the paragraphs added by `auto_p`.
+    paragraphs:      usize,
+    /// Whether the last `<p>` element is added by `auto_p` and has not been closed.
+    paragraph_open:  bool,
+    /// Whether the next content should start a line in the pretty mode.
+    line_break:      bool,
+    /// Whether the pending `<br>` element should start a line in the pretty mode. The line break is written with the `<br>` element, because it is not written if the `<br>` element is removed.
+    br_line_break:   bool,
+    /// Whether the whitespace before the next content is kept in the compact mode, such as the newline before a comment which starts a line, and the whitespace in an empty `<p>` element, which would be removed without it when the output is `auto_p`ed again.
+    keep_whitespace: bool,
+    /// The number of the bytes written after the last `<p>` start tag.
+    p_start_end:     Option<usize>,
+    /// The names of the open block-level elements in lowercase, for the indentation in the pretty mode.
+    open_blocks:     Vec<String>,
+    source_map:      Option<SourceMapBuilder>,
+}
+
+impl<'e, W: Write> Renderer<'e, W> {
+    #[inline]
+    pub(crate) fn new(engine: &'e AutoPEngine, out: W) -> Self {
+        Renderer {
+            engine,
+            out: Output {
+                inner: out, held: None, line_ending: "\n", written: 0
+            },
+            has_output: false,
+            whitespace: String::new(),
+            br: None,
+            last: Last::Nothing,
+            after_block: false,
+            collapse: Collapse::default(),
+            link_depth: 0,
+            paragraphs: 0,
+            paragraph_open: false,
+            line_break: false,
+            br_line_break: false,
+            keep_whitespace: false,
+            p_start_end: None,
+            open_blocks: Vec::new(),
+            source_map: None,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn out(&mut self) -> &mut W {
+        &mut self.out.inner
+    }
+
+    /// Write newlines with the dominant line ending of `html` if the `preserve_line_endings` option is enabled.
+    pub(crate) fn detect_line_ending(&mut self, html: &str) {
+        if self.engine.options().preserve_line_endings {
+            self.out.line_ending = dominant_line_ending(html);
+        }
+    }
+
+    /// Render the whole `html`.
+    pub(crate) fn render(mut self, html: &str) -> fmt::Result {
+        let options = self.engine.options();
+
+        if options.wordpress_compat {
+            return self.out.inner.write_str(&wpautop(html, options.br));
+        }
+
+        self.detect_line_ending(html);
+
+        self.render_tokens(&mut Tokenizer::new(self.engine, html))?;
+
+        self.finish()
+    }
+
+    /// Render the whole `html`, and map the output back to it. Nothing is mapped in the WordPress compatibility mode.
+    pub(crate) fn render_with_source_map(mut self, html: &str) -> Result<SourceMap, fmt::Error> {
+        if self.engine.options().wordpress_compat {
+            let source_map = SourceMapBuilder::new(html).build();
+
+            self.render(html)?;
+
+            return Ok(source_map);
+        }
+
+        self.source_map = Some(SourceMapBuilder::new(html));
+
+        self.detect_line_ending(html);
+        self.render_tokens(&mut Tokenizer::new(self.engine, html))?;
+        self.finish()?;
+
+        Ok(self.source_map.unwrap().build())
+    }
+
+    /// Render the paragraphs from `tokenizer`, and return the position where the unrendered input starts.
+    ///
+    /// If the tokenizer is partial, the last paragraph is not rendered because it may continue in the following input.
+    pub(crate) fn render_tokens(&mut self, tokenizer: &mut Tokenizer) -> Result<usize, fmt::Error> {
+        let mut tokens = Vec::new();
+        let mut position = 0;
+
+        // whitespace which may be removed because of the next token, with the offsets after it
+        let mut whitespace = Vec::new();
+        let mut collapse = self.collapse;
+
+        while let Some(token) = tokenizer.next() {
+            let token = match token {
+                _ if collapse.after && is_whitespace_token(&token) => continue,
+                Token::Text(text) if collapse.after => {
+                    Token::Text(text.trim_start_matches(is_whitespace))
+                },
+                token => token,
+            };
+
+            if is_whitespace_token(&token) {
+                whitespace.push((token, tokenizer.offset()));
+
+                if !collapse.is_inside() {
+                    if let Some(offset) = self.push_whitespace(&mut tokens, &mut whitespace)? {
+                        position = offset;
+                        self.collapse = collapse;
+                    }
+                }
+
+                continue;
+            }
+
+            if collapse.before(&token) {
+                whitespace.clear();
+
+                if let Some(Token::Text(text)) = tokens.last_mut() {
+                    *text = text.trim_end_matches(is_whitespace);
+                }
+            } else if let Some(offset) = self.push_whitespace(&mut tokens, &mut whitespace)? {
+                position = offset;
+                self.collapse = collapse;
+            }
+
+            collapse.update(&token);
+
+            if self.push_token(&mut tokens, token)? {
+                position = tokenizer.offset();
+                self.collapse = collapse;
+            }
+        }
+
+        if !tokenizer.is_partial() {
+            self.push_whitespace(&mut tokens, &mut whitespace)?;
+
+            self.paragraph(&tokens)?;
+
+            position = tokenizer.offset();
+            self.after_block = false;
+            self.collapse = collapse;
+        }
+
+        Ok(position)
+    }
+
+    /// Push the buffered `whitespace` like `push_token`. Return the offset after the last paragraph which ends in it.
+    fn push_whitespace<'a>(
+        &mut self,
+        tokens: &mut Vec<Token<'a>>,
+        whitespace: &mut Vec<(Token<'a>, usize)>,
+    ) -> Result<Option<usize>, fmt::Error> {
+        let mut position = None;
+
+        for (token, offset) in whitespace.drain(..) {
+            if self.push_token(tokens, token)? {
+                position = Some(offset);
+            }
+        }
+
+        Ok(position)
+    }
+
+    /// Push `token` to the tokens of the current paragraph, or render the paragraph if it ends. Return whether everything before and including `token` has been rendered.
+    fn push_token<'a>(
+        &mut self,
+        tokens: &mut Vec<Token<'a>>,
+        token: Token<'a>,
+    ) -> Result<bool, fmt::Error> {
+        match token {
+            Token::Newline if tokens.is_empty() && self.after_block => return Ok(false),
+            Token::Newline => {
+                if let Some(Token::Newline) = tokens.last() {
+                    tokens.pop();
+
+                    self.paragraph(tokens)?;
+
+                    tokens.clear();
+
+                    self.after_block = false;
+
+                    return Ok(true);
+                }
+            },
+            Token::Block(raw) => {
+                // a block ends the current paragraph
+                if !trim(tokens).is_empty() {
+                    self.paragraph(tokens)?;
+                }
+
+                tokens.clear();
+
+                self.separate();
+                self.standalone(&[Token::Block(raw)])?;
+
+                self.after_block = true;
+
+                return Ok(true);
+            },
+            _ => (),
+        }
+
+        tokens.push(token);
+
+        Ok(false)
+    }
+
+    /// Wrap `tokens` into a `<p>` element, fix the `<p>` tags around block elements and write it. Comments and shortcodes on their own lines at the start and the end are written outside the `<p>` element, and a paragraph which is a whole shortcode is written as it is.
+    fn paragraph(&mut self, tokens: &[Token]) -> fmt::Result {
+        let tokens = trim(tokens);
+
+        let (leading, tokens, trailing) = if is_whole_shortcode(tokens) {
+            (tokens, &[][..], &[][..])
+        } else {
+            split_standalone_lines(tokens)
+        };
+
+        let tokens = trim(tokens);
+
+        let paragraph = if tokens.is_empty() { Vec::new() } else { self.wrap(tokens) };
+
+        // empty paragraphs are dropped, so that consecutive blank lines are the same as one, like `wpautop` does
+        if leading.is_empty() && paragraph.is_empty() && trailing.is_empty() {
+            return Ok(());
+        }
+
+        self.separate();
+        self.standalone(leading)?;
+
+        let has_paragraph = !paragraph.is_empty();
+
+        if has_paragraph {
+            if !leading.is_empty() {
+                self.push_newline();
+            }
+
+            for token in paragraph {
+                self.token(token)?;
+            }
+        }
+
+        if !trailing.is_empty() {
+            if !leading.is_empty() || has_paragraph {
+                self.push_newline();
+            }
+
+            self.standalone(trailing)?;
+        }
+
+        Ok(())
+    }
+
+    /// Wrap `tokens` into a `<p>` element and fix the `<p>` tags around block elements.
+    fn wrap<'a>(&self, tokens: &[Token<'a>]) -> Vec<Token<'a>> {
+        let mut paragraph = Vec::with_capacity(tokens.len() + 4);
+
+        paragraph.push(Token::Tag(Tag::P_START));
+        paragraph.extend_from_slice(tokens);
+
+        if let Some(Token::Text(text)) = paragraph.get_mut(1) {
+            *text = text.trim_start();
+        }
+
+        if let Some(Token::Text(text)) = paragraph.last_mut() {
+            *text = text.trim_end();
+        }
+
+        paragraph.push(Token::Tag(Tag::P_END));
+
+        let paragraph = remove_empty_paragraphs(paragraph);
+        let paragraph = add_missing_p_start_tags(self.engine, paragraph);
+        let paragraph = add_missing_p_end_tags(self.engine, paragraph);
+        let paragraph = unwrap_li(paragraph);
+        let paragraph = remove_p_start_tags_before_blocks(self.engine, paragraph);
+        let paragraph = remove_p_end_tags_after_blocks(self.engine, paragraph);
+
+        if self.engine.options().xhtml {
+            balance_p_tags(self.engine, paragraph)
+        } else {
+            paragraph
+        }
+    }
+
+    /// Write `tokens` as they are, without `<p>` and `<br>` elements.
+    fn standalone(&mut self, tokens: &[Token]) -> fmt::Result {
+        for token in trim(tokens) {
+            match *token {
+                Token::Newline => self.push_newline(),
+                Token::Text(text) if text.trim().is_empty() => self.whitespace.push_str(text),
+                token => {
+                    self.line_break = true;
+                    self.keep_whitespace = self.whitespace.contains('\n');
+
+                    self.flush(true)?;
+
+                    match token {
+                        Token::Text(text) | Token::Block(text) => self.out.write_raw(text)?,
+                        Token::Comment(raw) => self.comment(raw)?,
+                        Token::Tag(tag) => self.tag(&tag)?,
+                        Token::Shortcode(shortcode) => self.out.write_raw(shortcode.raw)?,
+                        Token::Preserved(preserved) => self.preserved(preserved)?,
+                        Token::Newline => unreachable!(),
+                    }
+
+                    self.last = Last::Standalone;
+                    self.line_break = true;
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Buffer a newline. Adjacent newlines are merged, otherwise they would end the paragraph when the output is `auto_p`ed again.
+    #[inline]
+    fn push_newline(&mut self) {
+        if !self.whitespace.ends_with('\n') {
+            self.whitespace.push('\n');
+        }
+    }
+
+    /// Add the newline between the last written paragraph and the next one.
+    #[inline]
+    fn separate(&mut self) {
+        if self.has_output {
+            self.push_newline();
+        } else {
+            self.has_output = true;
+        }
+    }
+
+    /// Write out everything that has been buffered.
+    pub(crate) fn finish(&mut self) -> fmt::Result {
+        let length = self.whitespace.trim_end_matches('\n').len();
+
+        self.whitespace.truncate(length);
+        self.line_break = false;
+
+        self.flush(false)?;
+
+        self.release_held_paragraph(true)
+    }
+
+    fn token(&mut self, token: Token) -> fmt::Result {
+        match token {
+            Token::Newline => self.push_newline(),
+            Token::Text(text) => {
+                let content = text.trim_start_matches(is_whitespace);
+
+                self.whitespace.push_str(&text[..(text.len() - content.len())]);
+
+                if !content.is_empty() {
+                    let trailing = content.trim_end_matches(is_whitespace);
+
+                    self.flush(false)?;
+
+                    self.text(trailing)?;
+                    self.last = Last::Other;
+
+                    self.whitespace.push_str(&content[trailing.len()..]);
+                }
+            },
+            Token::Tag(tag) => {
+                if self.engine.options().br && tag.is_br() {
+                    let line_break = self.line_break && self.engine.options().pretty.is_some();
+
+                    // the whitespace after a block tag is left out in the compact mode, and replaced in the pretty mode
+                    let whitespace = if (self.engine.options().compact && self.last == Last::Block)
+                        || line_break
+                    {
+                        ""
+                    } else {
+                        self.whitespace.as_str()
+                    };
+
+                    let br = if self.br == Some(PendingBr::AfterBlock)
+                        && ((self.engine.options().compact && self.whitespace.starts_with('\n'))
+                            || (self.br_line_break && self.whitespace.contains('\n')))
+                    {
+                        // the `<br>` element before is removed with the whitespace after it, so this one is right after the block tag too
+                        PendingBr::AfterBlock
+                    } else if self.last == Last::Nothing || whitespace.contains('\n') {
+                        PendingBr::LineStart
+                    } else if !whitespace.is_empty() {
+                        if self.engine.options().compact || self.engine.options().pretty.is_some() {
+                            PendingBr::AfterSpace
+                        } else {
+                            PendingBr::Other
+                        }
+                    } else if self.br.is_some() {
+                        PendingBr::AfterBr
+                    } else if self.last == Last::Block {
+                        PendingBr::AfterBlock
+                    } else {
+                        PendingBr::Other
+                    };
+
+                    if self.br_line_break && br == PendingBr::AfterBlock {
+                        // the `<br>` element before is removed, and this one starts the line instead
+                        self.br = None;
+                        self.whitespace.clear();
+                    } else if line_break && self.br.is_none() {
+                        self.whitespace.clear();
+
+                        self.line_break = false;
+                        self.br_line_break = true;
+                    } else {
+                        self.flush(false)?;
+                    }
+
+                    self.br = Some(br);
+                    self.last = Last::Other;
+                } else {
+                    if tag.is("a") && !tag.self_closing {
+                        self.link_depth = if tag.end {
+                            self.link_depth.saturating_sub(1)
+                        } else {
+                            self.link_depth + 1
+                        };
+                    }
+
+                    let block = self.engine.is_block_tag_name(tag.name);
+                    let pretty = block && self.engine.options().pretty.is_some();
+                    let paragraph = self.is_paragraph_tag(&tag);
+
+                    if pretty {
+                        if tag.end {
+                            self.close_block(tag.name);
+                        }
+
+                        // `</p>` is on the same line as the content of the paragraph
+                        self.line_break = !(tag.end && paragraph);
+                    }
+
+                    if tag.end && paragraph {
+                        self.keep_whitespace = self.p_start_end == Some(self.out.written);
+                    }
+
+                    self.flush(block)?;
+
+                    if tag.is("p") && !self.engine.options().paragraph.is_plain_p() {
+                        self.p_tag(&tag)?;
+                    } else {
+                        self.tag(&tag)?;
+                    }
+
+                    if !tag.end && paragraph {
+                        self.p_start_end = Some(self.out.written);
+                    }
+
+                    if pretty {
+                        // the content of a paragraph is not indented
+                        if !tag.end
+                            && !tag.self_closing
+                            && !paragraph
+                            && !is_void_tag_name(tag.name)
+                        {
+                            self.open_blocks.push(tag.name.to_ascii_lowercase());
+                        }
+
+                        // the content of a paragraph is on the same line as `<p>`
+                        self.line_break = tag.end || !paragraph;
+                    }
+
+                    self.last = if block {
+                        Last::Block
+                    } else if tag.is_br() {
+                        Last::Br
+                    } else {
+                        Last::Other
+                    };
+                }
+            },
+            Token::Comment(raw)
+            | Token::Block(raw)
+            | Token::Shortcode(Shortcode {
+                raw, ..
+            }) => {
+                // a comment right after a comment which starts a line also starts the line
+                let line_start = matches!(self.last, Last::Nothing | Last::Standalone)
+                    || self.whitespace.contains('\n');
+
+                self.keep_whitespace = line_start;
+
+                self.flush(false)?;
+
+                if let Token::Comment(raw) = token {
+                    self.comment(raw)?;
+                } else {
+                    self.out.write_raw(raw)?;
+                }
+
+                self.last = if line_start { Last::Standalone } else { Last::Other };
+            },
+            Token::Preserved(preserved) => {
+                let block = self.engine.is_block_tag_name(preserved.start_tag.name);
+
+                // `<p>` tags are fixed around a preserved element like a block-level element
+                let block_like =
+                    block || self.engine.is_preserved_tag_name(preserved.start_tag.name);
+
+                self.line_break = block;
+
+                self.flush(block)?;
+
+                self.preserved(preserved)?;
+                self.last = if block_like { Last::Block } else { Last::Other };
+                self.line_break = block;
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Write text content, which is escaped in the plain text mode, and whose URLs and e-mail addresses are turned into links if enabled.
+    fn text(&mut self, text: &str) -> fmt::Result {
+        let linkify = self.link_depth == 0;
+
+        match &mut self.source_map {
+            Some(source_map) => {
+                // written into a buffer first to know whether the text is changed
+                let mut output = String::with_capacity(text.len());
+
+                write_text_with_links(&mut output, text, self.engine, linkify)?;
+
+                let start = self.out.written;
+
+                self.out.write_str(&output)?;
+
+                source_map.push(text, start..self.out.written, output == text);
+
+                Ok(())
+            },
+            None => write_text_with_links(&mut self.out, text, self.engine, linkify),
+        }
+    }
+
+    /// Whether `tag` is a `<p>` tag or a tag of the paragraph element, whose content is laid out like a paragraph in the pretty and compact modes.
+    #[inline]
+    fn is_paragraph_tag(&self, tag: &Tag) -> bool {
+        tag.is("p") || tag.is(self.engine.options().paragraph.valid_tag_name())
+    }
+
+    #[inline]
+    fn br_tag(&self) -> &'static str {
+        if self.engine.options().xhtml {
+            "<br />"
+        } else {
+            "<br>"
+        }
+    }
+
+    /// Write the buffered `<br>` element and whitespace before writing the next content. `block` is whether the next content starts with a block tag.
+    ///
+    /// If `br` is enabled, whitespace containing newlines is replaced with a `<br>` element followed by the last newline, except when it is between a block tag and other content, or after a comment which starts a line.
+    fn flush(&mut self, block: bool) -> fmt::Result {
+        if self.last == Last::Nothing {
+            // the output never starts with newlines
+            let length = self.whitespace.trim_start_matches('\n').len();
+
+            self.whitespace.drain(..(self.whitespace.len() - length));
+        }
+
+        let whitespace = std::mem::take(&mut self.whitespace);
+
+        // the newline after a comment or a shortcode which starts a line keeps it on its own line
+        let kept =
+            self.keep_whitespace || (self.last == Last::Standalone && whitespace.contains('\n'));
+
+        // whitespace at the boundaries of block-level elements or after a `<br>` element
+        let cosmetic =
+            !kept && (block || matches!(self.last, Last::Block | Last::Standalone | Last::Br));
+
+        if let Some(br) = self.br.take() {
+            // a `<br>` element with the whitespace after it
+            let removed = match br {
+                PendingBr::AfterBlock => {
+                    // whitespace which contains newlines is replaced with a newline in the pretty mode
+                    whitespace.starts_with('\n')
+                        || (self.engine.options().pretty.is_some() && whitespace.contains('\n'))
+                        || (block && self.is_line_end(&whitespace))
+                },
+                PendingBr::AfterBr | PendingBr::LineStart | PendingBr::AfterSpace => false,
+                PendingBr::Other => block && self.is_line_end(&whitespace),
+            };
+
+            let line_break = std::mem::take(&mut self.br_line_break);
+
+            if removed {
+                self.line_break |= line_break;
+            } else {
+                if line_break {
+                    self.write_whitespace("\n", false)?;
+                }
+
+                self.out.write_str(self.br_tag())?;
+            }
+
+            self.write_whitespace(&whitespace, !kept)?;
+        } else if self.engine.options().br {
+            if let Some(index) = whitespace.rfind('\n') {
+                if !(matches!(self.last, Last::Block | Last::Standalone)
+                    || (block && self.is_line_end(&whitespace[index..])))
+                {
+                    self.out.write_str(self.br_tag())?;
+
+                    self.write_whitespace(&whitespace[index..], !kept)?;
+                } else {
+                    self.write_whitespace(&whitespace, !kept)?;
+                }
+            } else {
+                self.write_whitespace(&whitespace, cosmetic)?;
+            }
+        } else {
+            self.write_whitespace(&whitespace, cosmetic)?;
+        }
+
+        self.whitespace = whitespace;
+        self.whitespace.clear();
+
+        self.line_break = false;
+        self.keep_whitespace = false;
+
+        Ok(())
+    }
+
+    /// Whether `whitespace` only ends a line. In the pretty mode, whitespace which contains newlines is replaced with a newline and the indentation, and any whitespace ends a line if the next content should start a line.
+    fn is_line_end(&self, whitespace: &str) -> bool {
+        if self.engine.options().pretty.is_some() {
+            self.line_break || whitespace.contains('\n')
+        } else {
+            whitespace == "\n"
+        }
+    }
+
+    /// Write `whitespace`. In the compact mode, it is skipped if it is `cosmetic`, which means it is around block-level elements or after a `<br>` element. In the pretty mode, it is replaced with a newline and the indentation if it contains newlines or the next content should start a line.
+    fn write_whitespace(&mut self, whitespace: &str, cosmetic: bool) -> fmt::Result {
+        let options = self.engine.options();
+
+        if options.compact && cosmetic {
+            return Ok(());
+        }
+
+        match options.pretty {
+            Some(indent) if self.line_break || whitespace.contains('\n') => {
+                if self.last == Last::Nothing {
+                    return Ok(());
+                }
+
+                self.out.write_char('\n')?;
+
+                for _ in 0..(indent * self.open_blocks.len().min(MAX_INDENT_DEPTH)) {
+                    self.out.write_char(' ')?;
+                }
+
+                Ok(())
+            },
+            _ => self.out.write_str(whitespace),
+        }
+    }
+
+    /// Write `tag`, which is converted into XHTML in the XHTML mode.
+    fn tag(&mut self, tag: &Tag) -> fmt::Result {
+        if self.engine.options().xhtml {
+            let mut raw = String::with_capacity(tag.raw.len() + 2);
+
+            write_xhtml_tag(&mut raw, tag)?;
+
+            write_with_newlines_normalized(&mut self.out, &raw)
+        } else {
+            write_with_newlines_normalized(&mut self.out, tag.raw)
+        }
+    }
+
+    /// Write a comment as it is, or as a well-formed XML comment in the XHTML mode.
+    #[inline]
+    fn comment(&mut self, raw: &str) -> fmt::Result {
+        if self.engine.options().xhtml {
+            write_xhtml_comment(&mut Raw(&mut self.out), raw)
+        } else {
+            self.out.write_raw(raw)
+        }
+    }
+
+    /// Write a `<p>` or `</p>` tag, using the paragraph element for the paragraphs added by `auto_p`.
+    fn p_tag(&mut self, tag: &Tag) -> fmt::Result {
+        let paragraph = &self.engine.options().paragraph;
+
+        if tag.end {
+            if self.paragraph_open {
+                self.paragraph_open = false;
+
+                write!(self.out, "</{}>", paragraph.valid_tag_name())
+            } else {
+                self.tag(tag)
+            }
+        } else if tag.generated {
+            self.paragraphs += 1;
+            self.paragraph_open = true;
+
+            if paragraph.last_class.is_some() {
+                self.release_held_paragraph(false)?;
+
+                self.out.held = Some((self.paragraphs, String::new()));
+
+                if let Some(source_map) = &mut self.source_map {
+                    source_map.hold();
+                }
+
+                Ok(())
+            } else {
+                write_paragraph_start_tag(&mut self.out, paragraph, self.paragraphs, false)
+            }
+        } else {
+            self.paragraph_open = false;
+
+            self.tag(tag)
+        }
+    }
+
+    /// Close the innermost open block-level element `name` and the elements in it, for the indentation in the pretty mode. Nothing is closed if `name` is not open.
+    fn close_block(&mut self, name: &str) {
+        if let Some(index) = self.open_blocks.iter().rposition(|e| e.eq_ignore_ascii_case(name)) {
+            self.open_blocks.truncate(index);
+        }
+    }
+
+    /// Write the held paragraph with its start tag.
+    fn release_held_paragraph(&mut self, last: bool) -> fmt::Result {
+        if let Some((index, content)) = self.out.held.take() {
+            let paragraph = &self.engine.options().paragraph;
+
+            let mut start_tag = String::new();
+
+            write_paragraph_start_tag(&mut start_tag, paragraph, index, last)?;
+
+            self.out.inner.write_str(&start_tag)?;
+            self.out.inner.write_str(&content)?;
+            self.out.written += start_tag.len();
+
+            if let Some(source_map) = &mut self.source_map {
+                source_map.release(start_tag.len());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn preserved(&mut self, preserved: Preserved) -> fmt::Result {
+        self.tag(&preserved.start_tag)?;
+
+        let options = self.engine.preserved_tag_options(preserved.start_tag.name);
+
+        let inner_html = if options.remove_useless_newlines {
+            trim_newline_exactly_one(preserved.inner_html)
+        } else {
+            preserved.inner_html
+        };
+
+        let xhtml = self.engine.options().xhtml;
+
+        match &mut self.source_map {
+            Some(source_map) => {
+                // written into a buffer first to know whether the inner HTML is changed
+                let mut output = String::with_capacity(inner_html.len());
+
+                write_inner_html(&mut output, &preserved.start_tag, inner_html, options, xhtml)?;
+
+                let start = self.out.written;
+
+                self.out.write_raw(&output)?;
+
+                source_map.push(inner_html, start..self.out.written, output == inner_html);
+            },
+            None => write_inner_html(
+                &mut Raw(&mut self.out),
+                &preserved.start_tag,
+                inner_html,
+                options,
+                xhtml,
+            )?,
+        }
+
+        write_with_newlines_normalized(&mut self.out, preserved.end_tag)
+    }
+}
+
+fn write_text_with_links<W: Write>(
+    out: &mut W,
+    mut text: &str,
+    engine: &AutoPEngine,
+    linkify: bool,
+) -> fmt::Result {
+    let options = engine.options();
+
+    if let Some(linkify_options) = engine.linkify_options().filter(|_| linkify) {
+        while let Some(link) = find_link(text) {
+            write_text(out, &text[..link.start], options)?;
+
+            let url = &text[link.start..link.end];
+
+            out.write_str("<a href=\"")?;
+            out.write_str(link.href_prefix)?;
+            write_text(out, url, options)?;
+            out.write_char('"')?;
+
+            let is_url = link.href_prefix != "mailto:";
+
+            if let Some(rel) = linkify_options.rel.as_deref().filter(|_| is_url) {
+                out.write_str(" rel=\"")?;
+                out.write_str(&html_escape::encode_double_quoted_attribute(rel))?;
+                out.write_char('"')?;
+            }
+
+            if let Some(target) = linkify_options.target.as_deref().filter(|_| is_url) {
+                out.write_str(" target=\"")?;
+                out.write_str(&html_escape::encode_double_quoted_attribute(target))?;
+                out.write_char('"')?;
+            }
+
+            out.write_char('>')?;
+            write_text(out, url, options)?;
+            out.write_str("</a>")?;
+
+            text = &text[link.end..];
+        }
+    }
+
+    write_text(out, text, options)
+}
+
+/// Write the inner HTML of a preserved element, which is escaped or converted depending on `options` and the `xhtml` option.
+fn write_inner_html<W: Write>(
+    out: &mut W,
+    start_tag: &Tag,
+    inner_html: &str,
+    options: PreservedTagOptions,
+    xhtml: bool,
+) -> fmt::Result {
+    if options.esc {
+        out.write_str(html_escape::encode_safe(inner_html).as_ref())
+    } else if xhtml && !start_tag.is("script") && !start_tag.is("style") {
+        // the inner HTML of `<textarea>` is text, and the inner HTML of `<svg>` is XML already
+        if start_tag.is("textarea") {
+            write_xhtml_text(out, inner_html)
+        } else if start_tag.is("svg") {
+            write_with_numeric_references(out, inner_html)
+        } else {
+            write_xhtml_inner_html(out, inner_html)
+        }
+    } else {
+        out.write_str(inner_html)
+    }
+}
+
+/// Find the most used line ending in `html`. `\n` wins ties, and then `\r\n`.
+fn dominant_line_ending(html: &str) -> &'static str {
+    let (mut lf, mut crlf, mut cr) = (0usize, 0usize, 0usize);
+
+    let mut bytes = html.bytes().peekable();
+
+    while let Some(e) = bytes.next() {
+        match e {
+            b'\n' => lf += 1,
+            b'\r' if bytes.peek() == Some(&b'\n') => {
+                bytes.next();
+
+                crlf += 1;
+            },
+            b'\r' => cr += 1,
+            _ => (),
+        }
+    }
+
+    if lf >= crlf && lf >= cr {
+        "\n"
+    } else if crlf >= cr {
+        "\r\n"
+    } else {
+        "\r"
+    }
+}
+
+#[inline]
+fn is_whitespace(c: char) -> bool {
+    c.is_ascii_whitespace()
+}
+
+/// Write the start tag of the `index`-th paragraph, counting from 1.
+fn write_paragraph_start_tag<W: Write>(
+    out: &mut W,
+    paragraph: &ParagraphElement,
+    index: usize,
+    last: bool,
+) -> fmt::Result {
+    let mut classes = Vec::new();
+
+    if index == 1 {
+        classes.extend(paragraph.first_class.as_deref());
+    }
+
+    if let Some((n, class)) = &paragraph.nth_class {
+        if *n == index {
+            classes.push(class.as_str());
+        }
+    }
+
+    if last {
+        classes.extend(paragraph.last_class.as_deref());
+    }
+
+    write!(out, "<{}", paragraph.valid_tag_name())?;
+
+    for (name, value) in
+        paragraph.attributes.iter().filter(|(name, _)| is_valid_attribute_name(name))
+    {
+        if name.eq_ignore_ascii_case("class") && !classes.is_empty() {
+            if !value.is_empty() {
+                classes.insert(0, value);
+            }
+
+            write_attribute(out, name, &classes.join(" "))?;
+
+            classes.clear();
+        } else {
+            write_attribute(out, name, value)?;
+        }
+    }
+
+    if !classes.is_empty() {
+        write_attribute(out, "class", &classes.join(" "))?;
+    }
+
+    out.write_char('>')
+}
+
+#[inline]
+fn write_attribute<W: Write>(out: &mut W, name: &str, value: &str) -> fmt::Result {
+    write!(out, " {}=\"{}\"", name, html_escape::encode_double_quoted_attribute(value))
+}
+
+#[inline]
+fn write_text<W: Write>(out: &mut W, text: &str, options: &Options) -> fmt::Result {
+    if options.plain_text {
+        out.write_str(&html_escape::encode_quoted_attribute(text))
+    } else if options.xhtml {
+        write_xhtml_text(out, text)
+    } else {
+        out.write_str(text)
+    }
+}
+
+#[inline]
+fn is_whitespace_token(token: &Token) -> bool {
+    match token {
+        Token::Newline => true,
+        Token::Text(text) => text.trim_start_matches(is_whitespace).is_empty(),
+        _ => false,
+    }
+}
+
+fn write_with_newlines_normalized<W: Write>(out: &mut W, s: &str) -> fmt::Result {
+    let mut chunks = s.split('\r');
+
+    if let Some(chunk) = chunks.next() {
+        out.write_str(chunk)?;
+    }
+
+    for chunk in chunks {
+        out.write_char('\n')?;
+        out.write_str(chunk.strip_prefix('\n').unwrap_or(chunk))?;
+    }
+
+    Ok(())
+}
+
+/// Remove leading and trailing tokens which are whitespace.
+fn trim<'a, 'b>(mut tokens: &'b [Token<'a>]) -> &'b [Token<'a>] {
+    while let Some(token) = tokens.first() {
+        match token {
+            Token::Newline => (),
+            Token::Text(text) if text.trim().is_empty() => (),
+            _ => break,
+        }
+
+        tokens = &tokens[1..];
+    }
+
+    while let Some(token) = tokens.last() {
+        match token {
+            Token::Newline => (),
+            Token::Text(text) if text.trim().is_empty() => (),
+            _ => break,
+        }
+
+        tokens = &tokens[..(tokens.len() - 1)];
+    }
+
+    tokens
+}
+
+/// Whether `tokens` are a self-closing shortcode, or an enclosing shortcode with its content, like `shortcode_unautop` in Wordpress.
+fn is_whole_shortcode(tokens: &[Token]) -> bool {
+    match tokens.first() {
+        Some(Token::Shortcode(first)) if !first.end => {
+            match tokens[1..].iter().position(
+                |e| matches!(e, Token::Shortcode(shortcode) if shortcode.end && shortcode.name == first.name),
+            ) {
+                Some(i) => i + 2 == tokens.len(),
+                None => tokens.len() == 1,
+            }
+        },
+        _ => false,
+    }
+}
+
+/// Split the lines which only have comments and shortcodes at the start and the end of `tokens`.
+fn split_standalone_lines<'a, 'b>(
+    tokens: &'b [Token<'a>],
+) -> (&'b [Token<'a>], &'b [Token<'a>], &'b [Token<'a>]) {
+    #[inline]
+    fn is_standalone_line_token(token: &Token) -> bool {
+        match token {
+            Token::Comment(_) | Token::Shortcode(_) => true,
+            Token::Text(text) => text.trim().is_empty(),
+            _ => false,
+        }
+    }
+
+    let is_standalone_line =
+        |line: &[Token]| line.iter().any(|e| matches!(e, Token::Comment(_) | Token::Shortcode(_)));
+
+    let mut start = 0;
+
+    loop {
+        let line_length =
+            tokens[start..].iter().take_while(|e| is_standalone_line_token(e)).count();
+
+        if !is_standalone_line(&tokens[start..(start + line_length)]) {
+            break;
+        }
+
+        match tokens.get(start + line_length) {
+            Some(Token::Newline) => start += line_length + 1,
+            Some(_) => break,
+            None => {
+                // the whole paragraph is standalone lines
+                return (tokens, &[], &[]);
+            },
+        }
+    }
+
+    let mut end = tokens.len();
+
+    loop {
+        let line_length =
+            tokens[start..end].iter().rev().take_while(|e| is_standalone_line_token(e)).count();
+
+        if !is_standalone_line(&tokens[(end - line_length)..end]) {
+            break;
+        }
+
+        match (end - line_length).checked_sub(1) {
+            Some(i) if i >= start && matches!(tokens[i], Token::Newline) => end = i,
+            _ => break,
+        }
+    }
+
+    (&tokens[..start], &tokens[start..end], &tokens[end..])
+}
+
+/// Whether the token is a text without any `<`, or a newline.
+#[inline]
+fn is_plain_text(token: &Token) -> bool {
+    match token {
+        Token::Newline => true,
+        Token::Text(text) => !text.contains('<'),
+        _ => false,
+    }
+}
+
+/// Count the leading plain text tokens of `tokens`, including the `<br>` elements which may be removed before a block tag.
+fn count_plain_text_and_br(engine: &AutoPEngine, tokens: &[Token]) -> usize {
+    let mut count = 0;
+
+    while let Some(token) = tokens.get(count) {
+        if !(is_plain_text(token) || is_removable_br(engine, tokens, count)) {
+            break;
+        }
+
+        count += 1;
+    }
+
+    count
+}
+
+/// Whether `tokens[index]` is a `<br>` element which may be removed before a block tag, which means it is followed by a newline. In the pretty mode, every `<br>` element may be removed, because a newline is written before a block tag.
+fn is_removable_br(engine: &AutoPEngine, tokens: &[Token], index: usize) -> bool {
+    matches!(tokens[index], Token::Tag(tag) if tag.is_br())
+        && (engine.options().pretty.is_some()
+            || tokens[(index + 1)..]
+                .iter()
+                .take_while(|e| is_whitespace_token(e))
+                .any(|e| matches!(e, Token::Newline)))
+}
+
+/// Whether the token is a block tag or a built-in element whose inner HTML is preserved.
+#[inline]
+fn is_block_or_preserved(engine: &AutoPEngine, token: &Token) -> bool {
+    let name = match token {
+        Token::Tag(tag) => tag.name,
+        Token::Preserved(preserved) => preserved.start_tag.name,
+        _ => return false,
+    };
+
+    engine.is_block_tag_name(name) || engine.is_preserved_tag_name(name)
+}
+
+#[inline]
+fn is_plain_p_start(token: &Token) -> bool {
+    matches!(token, Token::Tag(tag) if tag.is_plain_p_start())
+}
+
+#[inline]
+fn is_plain_p_end(token: &Token) -> bool {
+    matches!(token, Token::Tag(tag) if tag.is_plain_p_end())
+}
+
+/// Remove `<p></p>`. If only one of the two tags is added by `auto_p`, that one is kept instead, so that the added tags stay paired, and the kept `</p>` may make another `<p></p>` with the tag before it. The whitespace between a kept `<p>` and the content of its paragraph is removed, like when the tag is added, and the whitespace before a kept `</p>` is moved after it.
+fn remove_empty_paragraphs(tokens: Vec<Token>) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::with_capacity(tokens.len());
+    // whether a kept `<p>` is the last token
+    let mut after_p_start = false;
+
+    for token in tokens {
+        if after_p_start && is_whitespace_token(&token) {
+            continue;
+        }
+
+        after_p_start = false;
+
+        let mut token = Some(token);
+
+        while let Some(Token::Tag(b)) = token {
+            let a = match result.last() {
+                Some(Token::Tag(a)) if a.is_plain_p_start() && b.is_plain_p_end() => *a,
+                _ => break,
+            };
+
+            result.pop();
+
+            token = match (a.generated, b.generated) {
+                (true, false) => {
+                    result.push(Token::Tag(a));
+
+                    after_p_start = true;
+
+                    None
+                },
+                (false, true) => {
+                    let start =
+                        result.iter().rposition(|e| !is_whitespace_token(e)).map_or(0, |i| i + 1);
+
+                    let after_p_start = match start.checked_sub(1).and_then(|i| result.get(i)) {
+                        Some(Token::Tag(tag)) => tag.is_plain_p_start(),
+                        _ => false,
+                    };
+
+                    if start < result.len() && !after_p_start {
+                        // the whitespace before the removed `<p>` is moved after the kept `</p>`, so that it does not get wrapped into a paragraph
+                        let whitespace = result.split_off(start);
+
+                        result.push(Token::Tag(b));
+                        result.extend(whitespace);
+
+                        None
+                    } else {
+                        result.truncate(start);
+
+                        Some(Token::Tag(b))
+                    }
+                },
+                _ => None,
+            };
+        }
+
+        if let Some(token) = token {
+            result.push(token);
+        }
+    }
+
+    result
+}
+
+/// Add a starting `<p>` inside a block element if missing, such as `<div>text</p>`, also with a `<br>` element and a newline before the text.
+fn add_missing_p_start_tags<'a>(engine: &AutoPEngine, tokens: Vec<Token<'a>>) -> Vec<Token<'a>> {
+    let mut result = Vec::with_capacity(tokens.len() + 1);
+    let mut i = 0;
+
+    while i < tokens.len() {
+        if let Token::Tag(tag) = &tokens[i] {
+            if !tag.end && engine.is_block_tag_name_except_p(tag.name) {
+                // `<br>` elements which may be removed count as text, like in `add_missing_p_end_tags`
+                let j = i + 1 + count_plain_text_and_br(engine, &tokens[(i + 1)..]);
+
+                if j > i + 1 && tokens.get(j).map(is_plain_p_end) == Some(true) {
+                    result.push(tokens[i]);
+
+                    push_with_p_start_after_whitespace(&mut result, &tokens[(i + 1)..j]);
+
+                    result.push(tokens[j]);
+
+                    i = j + 1;
+
+                    continue;
+                }
+            }
+        }
+
+        result.push(tokens[i]);
+
+        i += 1;
+    }
+
+    result
+}
+
+/// Push `texts` and insert a `<p>` after the leading whitespace. If `texts` are all whitespace, the `<p>` is inserted before the last character.
+fn push_with_p_start_after_whitespace<'a>(result: &mut Vec<Token<'a>>, texts: &[Token<'a>]) {
+    for (i, token) in texts.iter().enumerate() {
+        if let Token::Text(text) = token {
+            let content = text.trim_start();
+
+            if !content.is_empty() {
+                let index = text.len() - content.len();
+
+                if index > 0 {
+                    result.push(Token::Text(&text[..index]));
+                }
+
+                result.push(Token::Tag(Tag::P_START));
+                result.push(Token::Text(content));
+                result.extend_from_slice(&texts[(i + 1)..]);
+
+                return;
+            }
+        }
+
+        result.push(*token);
+    }
+
+    // all whitespace
+    let last = result.pop().unwrap();
+
+    match last {
+        Token::Text(text) => {
+            let index = text.char_indices().next_back().unwrap().0;
+
+            if index > 0 {
+                result.push(Token::Text(&text[..index]));
+            }
+
+            result.push(Token::Tag(Tag::P_START));
+            result.push(Token::Text(&text[index..]));
+        },
+        _ => {
+            result.push(Token::Tag(Tag::P_START));
+            result.push(last);
+        },
+    }
+}
+
+/// Add a closing `</p>` inside a block element if missing, such as `<p>text</div>`, also with a `<br>` element and a newline before the end tag.
+fn add_missing_p_end_tags<'a>(engine: &AutoPEngine, tokens: Vec<Token<'a>>) -> Vec<Token<'a>> {
+    let mut result = Vec::with_capacity(tokens.len() + 1);
+    let mut i = 0;
+
+    while i < tokens.len() {
+        if is_plain_p_start(&tokens[i]) {
+            // `<br>` elements which may be removed count as text, otherwise the `</p>` tag would only be added when the output is `auto_p`ed again
+            let j = i + 1 + count_plain_text_and_br(engine, &tokens[(i + 1)..]);
+
+            if j > i + 1 {
+                if let Some(Token::Tag(tag)) = tokens.get(j) {
+                    if tag.is_bare_end() && engine.is_block_tag_name_except_p(tag.name) {
+                        result.push(Token::Tag(Tag::P_START));
+                        result.extend_from_slice(&tokens[(i + 1)..j]);
+                        result.push(Token::Tag(Tag::P_END));
+                        result.push(tokens[j]);
+
+                        i = j + 1;
+
+                        continue;
+                    }
+                }
+            }
+        }
+
+        result.push(tokens[i]);
+
+        i += 1;
+    }
+
+    result
+}
+
+/// In some cases `<li>` may get wrapped in `<p>`, fix them.
+fn unwrap_li(mut tokens: Vec<Token>) -> Vec<Token> {
+    let start = tokens.windows(2).position(|w| {
+        is_plain_p_start(&w[0]) && matches!(&w[1], Token::Tag(tag) if !tag.end && tag.is("li"))
+    });
+
+    if let Some(start) = start {
+        tokens.remove(start);
+
+        // remove the last `</p>` unless it closes a `<p>text` right before it
+        if let Some(end) = tokens.iter().rposition(is_plain_p_end) {
+            if end > start {
+                let text_start =
+                    end - tokens[start..end].iter().rev().take_while(|e| is_plain_text(e)).count();
+
+                if text_start == end
+                    || text_start == start
+                    || !is_plain_p_start(&tokens[text_start - 1])
+                {
+                    tokens.remove(end);
+                }
+            }
+        }
+    }
+
+    tokens
+}
+
+/// If an opening or closing block element tag is preceded by an opening `<p>` tag, remove the `<p>` tag. In the compact mode, a `<p>` tag added by `auto_p` is also removed with the `<br>` elements after it if there are only whitespace and `<br>` elements followed by newlines between them, such as `<p><br>\n<hr>`, because the `<br>` elements and the whitespace would be removed and the tags would become adjacent.
+fn remove_p_start_tags_before_blocks<'a>(
+    engine: &AutoPEngine,
+    tokens: Vec<Token<'a>>,
+) -> Vec<Token<'a>> {
+    let mut result = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+
+    while i < tokens.len() {
+        if is_plain_p_start(&tokens[i]) {
+            if let Some(next) = tokens.get(i + 1) {
+                if is_block_or_preserved(engine, next) {
+                    result.push(*next);
+
+                    i += 2;
+
+                    continue;
+                }
+            }
+
+            if engine.options().compact && matches!(tokens[i], Token::Tag(tag) if tag.generated) {
+                // a `<br>` element right after the `<p>` tag and a newline is removed, and so are the ones after it
+                let is_removed_br = |j: usize| {
+                    matches!(tokens[j], Token::Tag(tag) if tag.is_br())
+                        && matches!(tokens.get(j + 1), Some(Token::Newline))
+                };
+
+                let next = ((i + 1)..tokens.len())
+                    .find(|&j| !(is_whitespace_token(&tokens[j]) || is_removed_br(j)));
+
+                if let Some(j) = next {
+                    if is_block_or_preserved(engine, &tokens[j]) && !is_plain_p_end(&tokens[j]) {
+                        result
+                            .extend(((i + 1)..j).filter(|&k| !is_removed_br(k)).map(|k| tokens[k]));
+
+                        i = j;
+
+                        continue;
+                    }
+                }
+            }
+        }
+
+        result.push(tokens[i]);
+
+        i += 1;
+    }
+
+    result
+}
+
+/// If an opening or closing block element tag is followed by a closing `</p>` tag, remove the `</p>` tag.
+fn remove_p_end_tags_after_blocks<'a>(
+    engine: &AutoPEngine,
+    tokens: Vec<Token<'a>>,
+) -> Vec<Token<'a>> {
+    let mut result = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+
+    while i < tokens.len() {
+        result.push(tokens[i]);
+
+        // `<p class="x"></p>` is kept
+        if is_block_or_preserved(engine, &tokens[i])
+            && !matches!(tokens[i], Token::Tag(tag) if !tag.end && tag.is("p"))
+            && tokens.get(i + 1).map(is_plain_p_end) == Some(true)
+        {
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+
+    result
+}
+
+/// Make every `<p>` element closed before the next `<p>` element, the next block-level element and the end of the paragraph, and remove the `</p>` tags which close nothing. If the `</p>` added by `auto_p` closes nothing, a `<p>` is added before the content after the last block-level element instead, if there is such content.
+fn balance_p_tags<'a>(engine: &AutoPEngine, tokens: Vec<Token<'a>>) -> Vec<Token<'a>> {
+    let mut result: Vec<Token> = Vec::with_capacity(tokens.len() + 2);
+    let mut open = false;
+    // the index in `result` after the last block-level element
+    let mut block_end = 0;
+
+    for token in tokens {
+        match &token {
+            Token::Tag(tag) if tag.is("p") => {
+                if tag.end {
+                    if !open {
+                        match result[block_end..].iter().position(|e| !is_whitespace_token(e)) {
+                            Some(index) if tag.generated => {
+                                result.insert(block_end + index, Token::Tag(Tag::P_START))
+                            },
+                            _ => continue,
+                        }
+                    }
+
+                    open = false;
+                } else {
+                    if open {
+                        result.push(Token::Tag(Tag::P_END));
+                    }
+
+                    open = true;
+                }
+            },
+            token if open && is_block_or_preserved(engine, token) => {
+                result.push(Token::Tag(Tag::P_END));
+
+                open = false;
+            },
+            _ => (),
+        }
+
+        let block = is_block_or_preserved(engine, &token);
+
+        result.push(token);
+
+        if block {
+            block_end = result.len();
+        }
+    }
+
+    if open {
+        result.push(Token::Tag(Tag::P_END));
+    }
+
+    result
+}
@@ -0,0 +1,126 @@
+use std::ops::Range;
+
+/// A piece of the output which comes from a piece of the input.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Mapping {
+    /// The byte range in the input.
+    pub input:    Range<usize>,
+    /// The byte range in the output.
+    pub output:   Range<usize>,
+    /// Whether the piece is copied as it is. If not, it has been escaped or converted (such as `&nbsp;` into `&#160;`), even if the two ranges have the same length.
+    pub verbatim: bool,
+}
+
+/// The mapping between the byte offsets of the output of `auto_p_with_map` and the input. It covers every text run and the inner HTML of every preserved element (such as `<pre>` and `<script>`). Tags and whitespace added or changed by `auto_p` are not covered.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SourceMap {
+    /// Sorted by both the input ranges and the output ranges.
+    mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    /// All mappings, in the order of the input and the output.
+    #[inline]
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    /// Find the mapping whose output range contains `output`.
+    #[inline]
+    pub fn find_by_output(&self, output: usize) -> Option<&Mapping> {
+        let index = self.mappings.partition_point(|e| e.output.end <= output);
+
+        self.mappings.get(index).filter(|e| e.output.start <= output)
+    }
+
+    /// Find the mapping whose input range contains `input`.
+    #[inline]
+    pub fn find_by_input(&self, input: usize) -> Option<&Mapping> {
+        let index = self.mappings.partition_point(|e| e.input.end <= input);
+
+        self.mappings.get(index).filter(|e| e.input.start <= input)
+    }
+
+    /// Map an offset in the output to the offset in the input. If the mapped piece is not verbatim, the start of its input range is returned.
+    pub fn input_offset(&self, output: usize) -> Option<usize> {
+        self.find_by_output(output).map(|e| {
+            if e.verbatim {
+                e.input.start + (output - e.output.start)
+            } else {
+                e.input.start
+            }
+        })
+    }
+
+    /// Map an offset in the input to the offset in the output. If the mapped piece is not verbatim, the start of its output range is returned.
+    pub fn output_offset(&self, input: usize) -> Option<usize> {
+        self.find_by_input(input).map(|e| {
+            if e.verbatim {
+                e.output.start + (input - e.input.start)
+            } else {
+                e.output.start
+            }
+        })
+    }
+}
+
+/// Collects the mappings while rendering.
+#[derive(Debug)]
+pub(crate) struct SourceMapBuilder {
+    /// The address of the input.
+    base:     usize,
+    length:   usize,
+    mappings: Vec<Mapping>,
+    /// The index of the first mapping in the held paragraph.
+    held:     Option<usize>,
+}
+
+impl SourceMapBuilder {
+    #[inline]
+    pub(crate) fn new(input: &str) -> Self {
+        SourceMapBuilder {
+            base:     input.as_ptr() as usize,
+            length:   input.len(),
+            mappings: Vec::new(),
+            held:     None,
+        }
+    }
+
+    /// Add a mapping from `input`, which should be a slice of the input, to the `output` range, which is the same as `input` if it is `verbatim`. Empty pieces and strings which are not in the input are ignored.
+    pub(crate) fn push(&mut self, input: &str, output: Range<usize>, verbatim: bool) {
+        let start = (input.as_ptr() as usize).wrapping_sub(self.base);
+
+        if input.is_empty() || start >= self.length {
+            return;
+        }
+
+        self.mappings.push(Mapping {
+            input: start..(start + input.len()),
+            output,
+            verbatim,
+        });
+    }
+
+    /// Mark the start of a paragraph whose start tag is written later.
+    #[inline]
+    pub(crate) fn hold(&mut self) {
+        self.held = Some(self.mappings.len());
+    }
+
+    /// Shift the mappings of the held paragraph by the `length` of its start tag.
+    pub(crate) fn release(&mut self, length: usize) {
+        if let Some(index) = self.held.take() {
+            for mapping in &mut self.mappings[index..] {
+                mapping.output.start += length;
+                mapping.output.end += length;
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn build(self) -> SourceMap {
+        SourceMap {
+            mappings: self.mappings
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+    str::{from_utf8, from_utf8_unchecked},
+};
+
+use crate::{renderer::Renderer, tokenizer::Tokenizer, wordpress::wpautop, AutoPEngine, Options};
+
+const BUFFER_SIZE: usize = 8192;
+
+/// Adapts an `io::Write` to a `fmt::Write`, keeping the I/O error.
+struct IoWriter<W: Write> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> fmt::Write for IoWriter<W> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|error| {
+            self.error = Some(error);
+
+            fmt::Error
+        })
+    }
+}
+
+impl<W: Write> IoWriter<W> {
+    #[inline]
+    fn take_error(&mut self) -> io::Error {
+        self.error.take().unwrap_or_else(|| io::Error::new(ErrorKind::Other, "formatter error"))
+    }
+}
+
+/// Like the `auto_p` function, but read the HTML from `reader` and write the result to `writer`.
+///
+/// Every paragraph is written out as soon as the blank line after it has been read, so only the current paragraph is buffered. An element whose inner HTML is preserved, such as `<pre>`, is buffered until its end tag has been read, and so is a block in the Gutenberg mode until its closing comment.
+///
+/// With the `preserve_line_endings` option, the line ending is determined by the first 8 KiB of the input.
+///
+/// The `writer` is not buffered by this function. Wrap it in a `BufWriter` if needed.
+#[inline]
+pub fn auto_p_stream<R: Read, W: Write>(reader: R, writer: W, options: Options) -> io::Result<()> {
+    AutoPEngine::new(options).auto_p_stream(reader, writer)
+}
+
+pub(crate) fn render_stream<R: Read, W: Write>(
+    engine: &AutoPEngine,
+    mut reader: R,
+    mut writer: W,
+) -> io::Result<()> {
+    if engine.options().wordpress_compat {
+        // `wpautop` works on the whole input
+        let mut html = String::new();
+
+        reader.read_to_string(&mut html)?;
+
+        writer.write_all(wpautop(&html, engine.options().br).as_bytes())?;
+
+        return writer.flush();
+    }
+
+    let mut renderer = Renderer::new(engine, IoWriter {
+        inner: writer, error: None
+    });
+
+    let mut buffer: Vec<u8> = Vec::with_capacity(BUFFER_SIZE);
+    let mut eof = false;
+    let mut line_ending_detected = false;
+
+    // the length the buffer should exceed before tokenizing again
+    let mut threshold = if engine.options().preserve_line_endings {
+        // read a whole buffer first to find the dominant line ending
+        BUFFER_SIZE - 1
+    } else {
+        0
+    };
+
+    loop {
+        while !eof && buffer.len() <= threshold {
+            let length = buffer.len();
+
+            buffer.resize(length + BUFFER_SIZE, 0);
+
+            match reader.read(&mut buffer[length..]) {
+                Ok(c) => {
+                    buffer.truncate(length + c);
+
+                    eof = c == 0;
+                },
+                Err(error) if error.kind() == ErrorKind::Interrupted => {
+                    buffer.truncate(length);
+                },
+                Err(error) => return Err(error),
+            }
+        }
+
+        let html = match from_utf8(&buffer) {
+            Ok(html) => html,
+            Err(error) if !eof && error.error_len().is_none() => {
+                // an incomplete UTF-8 sequence at the end
+                unsafe { from_utf8_unchecked(&buffer[..error.valid_up_to()]) }
+            },
+            Err(error) => return Err(io::Error::new(ErrorKind::InvalidData, error)),
+        };
+
+        if !line_ending_detected {
+            renderer.detect_line_ending(html);
+
+            line_ending_detected = true;
+        }
+
+        if eof {
+            renderer
+                .render_tokens(&mut Tokenizer::new(engine, html))
+                .map_err(|_| renderer.out().take_error())?;
+
+            break;
+        }
+
+        let position = renderer
+            .render_tokens(&mut Tokenizer::partial(engine, html))
+            .map_err(|_| renderer.out().take_error())?;
+
+        buffer.drain(..position);
+
+        // if a long paragraph has not ended, wait for the buffer to grow twice as long, so that the paragraph is not tokenized too many times
+        threshold = if position == 0 && buffer.len() >= BUFFER_SIZE {
+            buffer.len() * 2
+        } else {
+            buffer.len()
+        };
+    }
+
+    renderer.finish().map_err(|_| renderer.out().take_error())?;
+
+    renderer.out().inner.flush()
+}
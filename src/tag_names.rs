@@ -0,0 +1,106 @@
+/// Names of the block-level elements except `<p>`.
+pub(crate) const BLOCK_TAG_NAMES: [&str; 45] = [
+    "table",
+    "thead",
+    "tfoot",
+    "caption",
+    "col",
+    "colgroup",
+    "tbody",
+    "tr",
+    "td",
+    "th",
+    "div",
+    "dl",
+    "dd",
+    "dt",
+    "ul",
+    "ol",
+    "li",
+    "pre",
+    "form",
+    "map",
+    "area",
+    "blockquote",
+    "address",
+    "math",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "fieldset",
+    "legend",
+    "section",
+    "article",
+    "aside",
+    "hgroup",
+    "header",
+    "footer",
+    "nav",
+    "figure",
+    "figcaption",
+    "details",
+    "menu",
+    "summary",
+];
+
+/// Names of the elements whose inner HTML should not get `auto_p`ed.
+pub(crate) const PRESERVED_TAG_NAMES: [&str; 5] = ["pre", "textarea", "script", "style", "svg"];
+
+/// Names of the void elements, which have no end tags.
+pub(crate) const VOID_TAG_NAMES: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Names of the elements whose end tags may be omitted.
+pub(crate) const OPTIONAL_END_TAG_NAMES: [&str; 16] = [
+    "p", "li", "dt", "dd", "option", "optgroup", "tr", "td", "th", "thead", "tbody", "tfoot",
+    "colgroup", "caption", "rt", "rp",
+];
+
+/// Whether `name` is a block-level element except `<p>`.
+#[inline]
+pub(crate) fn is_block_tag_name_except_p(name: &str) -> bool {
+    BLOCK_TAG_NAMES.iter().any(|e| e.eq_ignore_ascii_case(name))
+}
+
+/// Whether `name` is a custom element name, which starts with an ASCII letter and contains a hyphen.
+#[inline]
+pub(crate) fn is_custom_element_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic()) && name.contains('-')
+}
+
+#[inline]
+pub(crate) fn is_preserved_tag_name(name: &str) -> bool {
+    PRESERVED_TAG_NAMES.iter().any(|e| e.eq_ignore_ascii_case(name))
+}
+
+#[inline]
+pub(crate) fn is_void_tag_name(name: &str) -> bool {
+    VOID_TAG_NAMES.iter().any(|e| e.eq_ignore_ascii_case(name))
+}
+
+#[inline]
+pub(crate) fn is_optional_end_tag_name(name: &str) -> bool {
+    OPTIONAL_END_TAG_NAMES.iter().any(|e| e.eq_ignore_ascii_case(name))
+}
+
+/// Whether `name` is a valid tag name in HTML, which starts with an ASCII letter and has no whitespace, `/`, `>`, `<`, `=`, quotes or control characters.
+pub(crate) fn is_valid_tag_name(name: &str) -> bool {
+    name.as_bytes().first().map_or(false, u8::is_ascii_alphabetic) && is_valid_attribute_name(name)
+}
+
+/// Whether `name` is a valid attribute name in HTML, which is not empty and has no whitespace, `/`, `>`, `<`, `=`, quotes, control characters or noncharacters.
+pub(crate) fn is_valid_attribute_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().all(|c| {
+            !c.is_control()
+                && !matches!(c, ' ' | '/' | '>' | '<' | '=' | '"' | '\'')
+                && !matches!(c, '\u{FDD0}'..='\u{FDEF}')
+                && (c as u32) & 0xFFFE != 0xFFFE
+        })
+}
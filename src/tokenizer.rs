@@ -0,0 +1,628 @@
+use crate::{
+    gutenberg::{find_block_end, parse_delimiter, DelimiterKind},
+    AutoPEngine,
+};
+
+/// A start tag or an end tag.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Tag<'a> {
+    /// The whole tag, including `<` and `>`.
+    pub(crate) raw:          &'a str,
+    /// The tag name, with its original case.
+    pub(crate) name:         &'a str,
+    /// Everything between the tag name and `>` (or `/>`).
+    pub(crate) attributes:   &'a str,
+    /// Whether this is an end tag (`</name>`).
+    pub(crate) end:          bool,
+    /// Whether this tag ends with `/>`.
+    pub(crate) self_closing: bool,
+    /// Whether this tag is added by `auto_p` instead of being in the input.
+    pub(crate) generated:    bool,
+}
+
+impl<'a> Tag<'a> {
+    pub(crate) const P_END: Tag<'static> = Tag {
+        raw:          "</p>",
+        name:         "p",
+        attributes:   "",
+        end:          true,
+        self_closing: false,
+        generated:    true,
+    };
+    pub(crate) const P_START: Tag<'static> = Tag {
+        raw:          "<p>",
+        name:         "p",
+        attributes:   "",
+        end:          false,
+        self_closing: false,
+        generated:    true,
+    };
+
+    #[inline]
+    pub(crate) fn is(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+
+    /// Whether this tag is exactly `<p>` (case-insensitively).
+    #[inline]
+    pub(crate) fn is_plain_p_start(&self) -> bool {
+        !self.end && self.raw.eq_ignore_ascii_case("<p>")
+    }
+
+    /// Whether this tag is exactly `</p>` (case-insensitively).
+    #[inline]
+    pub(crate) fn is_plain_p_end(&self) -> bool {
+        self.end && self.raw.eq_ignore_ascii_case("</p>")
+    }
+
+    /// Whether this tag is a `<br>` element which can be normalized, such as `<br/>` or `<BR />`.
+    #[inline]
+    pub(crate) fn is_br(&self) -> bool {
+        !self.end && self.is("br") && self.attributes.trim_matches(is_whitespace).is_empty()
+    }
+
+    /// Whether this tag is an end tag without any attribute, such as `</div >`.
+    #[inline]
+    pub(crate) fn is_bare_end(&self) -> bool {
+        self.end && !self.self_closing && self.attributes.trim_matches(is_whitespace).is_empty()
+    }
+
+    /// The attributes of this tag as names and values, without the quotes. The value of an attribute without `=` (such as `disabled`) is `None`.
+    #[inline]
+    pub(crate) fn attributes(&self) -> Attributes<'a> {
+        Attributes {
+            rest: self.attributes
+        }
+    }
+}
+
+/// An iterator over the attributes of a tag, which has been checked by `parse_tag`.
+#[derive(Debug, Clone)]
+pub(crate) struct Attributes<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Attributes<'a> {
+    type Item = (&'a str, Option<&'a str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = self.rest.trim_start_matches(is_whitespace);
+
+        if s.is_empty() {
+            return None;
+        }
+
+        let name_length = s.find(|c: char| c == '=' || is_whitespace(c)).unwrap_or(s.len());
+        let name = &s[..name_length];
+
+        let rest = &s[name_length..];
+
+        match rest.strip_prefix('=') {
+            Some(value) => {
+                let (value, rest) = match value.chars().next() {
+                    Some(quote) if quote == '"' || quote == '\'' => {
+                        let end = value[1..].find(quote).map_or(value.len(), |i| i + 1);
+
+                        (&value[1..end], value.get((end + 1)..).unwrap_or(""))
+                    },
+                    _ => {
+                        let end = value.find(is_whitespace).unwrap_or(value.len());
+
+                        (&value[..end], &value[end..])
+                    },
+                };
+
+                self.rest = rest;
+
+                Some((name, Some(value)))
+            },
+            None => {
+                self.rest = rest;
+
+                Some((name, None))
+            },
+        }
+    }
+}
+
+/// An opening or closing shortcode, such as `[gallery ids="1,2"]` and `[/caption]`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Shortcode<'a> {
+    /// The whole shortcode, including `[` and `]`.
+    pub(crate) raw:  &'a str,
+    pub(crate) name: &'a str,
+    /// Whether this is a closing shortcode (`[/name]`).
+    pub(crate) end:  bool,
+}
+
+/// An element whose inner HTML should be kept as it is, such as `<pre>`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Preserved<'a> {
+    pub(crate) start_tag:  Tag<'a>,
+    pub(crate) inner_html: &'a str,
+    pub(crate) end_tag:    &'a str,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Token<'a> {
+    /// Text without newline characters.
+    Text(&'a str),
+    /// `"\n"`, `"\r\n"` or `"\r"`.
+    Newline,
+    Tag(Tag<'a>),
+    Preserved(Preserved<'a>),
+    /// A whole comment, including `<!--` and `-->`.
+    Comment(&'a str),
+    /// A registered shortcode. Its attributes may contain newlines.
+    Shortcode(Shortcode<'a>),
+    /// A whole block serialized by the block editor, including the block comment delimiters. Only in the Gutenberg mode.
+    Block(&'a str),
+}
+
+/// Why a `<` does not start a tag.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum NotTag {
+    /// The characters after `<` cannot form a tag.
+    Invalid,
+    /// The input ends before the tag does.
+    Incomplete,
+}
+
+/// Splits HTML into tokens with a single forward scan.
+#[derive(Debug, Clone)]
+pub(crate) struct Tokenizer<'a> {
+    engine:         &'a AutoPEngine,
+    html:           &'a str,
+    p:              usize,
+    /// Whether `html` may be followed by more input. If it is, the tokenizer stops before a token which may continue in the following input.
+    partial:        bool,
+    /// Preserved tag names which have been known to have no more end tags after `p`.
+    unclosed_names: Vec<&'a str>,
+    /// A tag which has been parsed while scanning text.
+    peeked_tag:     Option<Tag<'a>>,
+}
+
+impl<'a> Tokenizer<'a> {
+    #[inline]
+    pub(crate) fn new(engine: &'a AutoPEngine, html: &'a str) -> Self {
+        Tokenizer {
+            engine,
+            html,
+            p: 0,
+            partial: false,
+            unclosed_names: Vec::new(),
+            peeked_tag: None,
+        }
+    }
+
+    /// Create a tokenizer for `html` which may be followed by more input.
+    #[inline]
+    pub(crate) fn partial(engine: &'a AutoPEngine, html: &'a str) -> Self {
+        Tokenizer {
+            partial: true,
+            ..Tokenizer::new(engine, html)
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_partial(&self) -> bool {
+        self.partial
+    }
+
+    /// The byte offset of the next token.
+    #[inline]
+    pub(crate) fn offset(&self) -> usize {
+        self.p
+    }
+
+    /// Whether the `[` at `p` may start a shortcode. `[[name]]` is an escaped shortcode.
+    #[inline]
+    fn is_shortcode_start(&self, p: usize) -> bool {
+        self.engine.has_shortcodes() && (p == 0 || self.html.as_bytes()[p - 1] != b'[')
+    }
+
+    /// Read a newline, which is `\n`, `\r\n` or `\r`.
+    #[inline]
+    fn newline(&mut self) -> Option<Token<'a>> {
+        let bytes = self.html.as_bytes();
+
+        if bytes[self.p] == b'\r' {
+            if self.p + 1 == bytes.len() && self.partial {
+                return None;
+            }
+
+            self.p += if bytes.get(self.p + 1) == Some(&b'\n') { 2 } else { 1 };
+        } else {
+            self.p += 1;
+        }
+
+        Some(Token::Newline)
+    }
+
+    /// Read a newline or a line of text, ignoring tags, comments and shortcodes.
+    fn next_plain_text(&mut self) -> Option<Token<'a>> {
+        let rest = &self.html[self.p..];
+
+        match rest.find(&['\n', '\r'][..]) {
+            Some(0) => self.newline(),
+            Some(length) => {
+                self.p += length;
+
+                Some(Token::Text(&rest[..length]))
+            },
+            None => {
+                self.p = self.html.len();
+
+                Some(Token::Text(rest))
+            },
+        }
+    }
+
+    fn preserved(&mut self, start_tag: Tag<'a>) -> Result<Option<Preserved<'a>>, NotTag> {
+        if start_tag.end
+            || start_tag.self_closing
+            || !self.engine.is_preserved_tag_name(start_tag.name)
+            || self.unclosed_names.iter().any(|name| name.eq_ignore_ascii_case(start_tag.name))
+        {
+            return Ok(None);
+        }
+
+        let inner_start = self.p + start_tag.raw.len();
+
+        match find_end_tag(&self.html[inner_start..], start_tag.name) {
+            Some((inner_length, end_tag_length)) => {
+                let inner_end = inner_start + inner_length;
+
+                Ok(Some(Preserved {
+                    start_tag,
+                    inner_html: &self.html[inner_start..inner_end],
+                    end_tag: &self.html[inner_end..(inner_end + end_tag_length)],
+                }))
+            },
+            None if self.partial => Err(NotTag::Incomplete),
+            None => {
+                self.unclosed_names.push(start_tag.name);
+
+                Ok(None)
+            },
+        }
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let bytes = self.html.as_bytes();
+        let length = bytes.len();
+
+        if self.p >= length {
+            return None;
+        }
+
+        if self.engine.options().plain_text {
+            return self.next_plain_text();
+        }
+
+        let tag = match self.peeked_tag.take() {
+            Some(tag) => Some(tag),
+            None => match bytes[self.p] {
+                b'\n' | b'\r' => return self.newline(),
+                b'[' if self.is_shortcode_start(self.p) => {
+                    match parse_shortcode(&self.html[self.p..], self.engine) {
+                        Ok(shortcode) => {
+                            self.p += shortcode.raw.len();
+
+                            return Some(Token::Shortcode(shortcode));
+                        },
+                        Err(NotTag::Incomplete) if self.partial => return None,
+                        Err(_) => None,
+                    }
+                },
+                b'<' if bytes[self.p..].starts_with(b"<!--") => {
+                    let length = match find_comment_end(&self.html[self.p..]) {
+                        Some(length) => length,
+                        None if self.partial => return None,
+                        // an unclosed comment lasts until the end of the input
+                        None => length - self.p,
+                    };
+
+                    let comment = &self.html[self.p..(self.p + length)];
+
+                    let token = if self.engine.options().gutenberg {
+                        match parse_delimiter(comment).map(|e| e.kind) {
+                            Some(DelimiterKind::Opener) => {
+                                let rest = &self.html[self.p..];
+
+                                match find_block_end(rest, length) {
+                                    Some(length) => Token::Block(&rest[..length]),
+                                    None if self.partial => return None,
+                                    // an unclosed block lasts until the end of the input
+                                    None => Token::Block(rest),
+                                }
+                            },
+                            Some(DelimiterKind::Void) => Token::Block(comment),
+                            _ => Token::Comment(comment),
+                        }
+                    } else {
+                        Token::Comment(comment)
+                    };
+
+                    self.p += match token {
+                        Token::Block(block) => block.len(),
+                        _ => length,
+                    };
+
+                    return Some(token);
+                },
+                b'<' => match parse_tag(&self.html[self.p..]) {
+                    Ok(tag) => Some(tag),
+                    Err(NotTag::Incomplete) if self.partial => return None,
+                    Err(_) => None,
+                },
+                _ => None,
+            },
+        };
+
+        if let Some(tag) = tag {
+            let token = match self.preserved(tag).ok()? {
+                Some(preserved) => {
+                    self.p += preserved.start_tag.raw.len()
+                        + preserved.inner_html.len()
+                        + preserved.end_tag.len();
+
+                    Token::Preserved(preserved)
+                },
+                None => {
+                    self.p += tag.raw.len();
+
+                    Token::Tag(tag)
+                },
+            };
+
+            return Some(token);
+        }
+
+        // text
+        let start = self.p;
+        let mut p = start + 1;
+
+        while p < length {
+            match bytes[p] {
+                b'\n' | b'\r' => break,
+                b'<' if bytes[p..].starts_with(b"<!--") => break,
+                b'[' if self.is_shortcode_start(p) => {
+                    match parse_shortcode(&self.html[p..], self.engine) {
+                        Ok(_) => break,
+                        Err(NotTag::Incomplete) if self.partial => break,
+                        Err(_) => (),
+                    }
+                },
+                b'<' => match parse_tag(&self.html[p..]) {
+                    Ok(tag) => {
+                        self.peeked_tag = Some(tag);
+
+                        break;
+                    },
+                    Err(NotTag::Incomplete) if self.partial => break,
+                    Err(_) => (),
+                },
+                _ => (),
+            }
+
+            p += 1;
+        }
+
+        self.p = p;
+
+        Some(Token::Text(&self.html[start..p]))
+    }
+}
+
+#[inline]
+pub(crate) fn is_whitespace(c: char) -> bool {
+    c.is_ascii_whitespace()
+}
+
+/// Parse a start tag or an end tag at the beginning of `s`.
+///
+/// An attribute value can be quoted with `"` or `'`, and the quoted value may contain `<`, `>` and newlines.
+pub(crate) fn parse_tag(s: &str) -> Result<Tag<'_>, NotTag> {
+    let bytes = s.as_bytes();
+    let length = bytes.len();
+
+    debug_assert_eq!(b'<', bytes[0]);
+
+    let mut p = 1;
+
+    let end = bytes.get(p) == Some(&b'/');
+
+    if end {
+        p += 1;
+    }
+
+    // name
+    let name_start = p;
+
+    while p < length {
+        match bytes[p] {
+            b'<' | b'>' => break,
+            b'/' if bytes.get(p + 1) == Some(&b'>') => break,
+            e if e.is_ascii_whitespace() => break,
+            _ => p += 1,
+        }
+    }
+
+    if p == length {
+        return Err(NotTag::Incomplete);
+    }
+
+    if p == name_start {
+        return Err(NotTag::Invalid);
+    }
+
+    let name_end = p;
+
+    // attributes
+    loop {
+        let whitespace_start = p;
+
+        while p < length && bytes[p].is_ascii_whitespace() {
+            p += 1;
+        }
+
+        match bytes.get(p) {
+            Some(b'>') => {
+                return Ok(Tag {
+                    raw: &s[..=p],
+                    name: &s[name_start..name_end],
+                    attributes: &s[name_end..p],
+                    end,
+                    self_closing: false,
+                    generated: false,
+                });
+            },
+            Some(b'/') if bytes.get(p + 1) == Some(&b'>') => {
+                return Ok(Tag {
+                    raw: &s[..(p + 2)],
+                    name: &s[name_start..name_end],
+                    attributes: &s[name_end..p],
+                    end,
+                    self_closing: true,
+                    generated: false,
+                });
+            },
+            Some(_) if p > whitespace_start => (),
+            Some(_) => return Err(NotTag::Invalid),
+            None => return Err(NotTag::Incomplete),
+        }
+
+        // attribute name
+        let attribute_name_start = p;
+
+        while p < length {
+            match bytes[p] {
+                b'<' | b'>' | b'=' => break,
+                e if e.is_ascii_whitespace() => break,
+                _ => p += 1,
+            }
+        }
+
+        if p == attribute_name_start {
+            return Err(NotTag::Invalid);
+        }
+
+        // attribute value
+        if bytes.get(p) == Some(&b'=') {
+            p += 1;
+
+            match bytes.get(p) {
+                Some(&quote) if quote == b'"' || quote == b'\'' => {
+                    p += 1;
+
+                    match bytes[p..].iter().position(|&e| e == quote) {
+                        Some(i) => p += i + 1,
+                        None => return Err(NotTag::Incomplete),
+                    }
+                },
+                _ => {
+                    while p < length {
+                        match bytes[p] {
+                            b'<' | b'>' => break,
+                            e if e.is_ascii_whitespace() => break,
+                            _ => p += 1,
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Parse an opening or closing shortcode at the beginning of `s`, whose name is registered in `engine`.
+///
+/// Like Wordpress, the attributes cannot contain `]`, even if it is quoted.
+fn parse_shortcode<'a>(s: &'a str, engine: &AutoPEngine) -> Result<Shortcode<'a>, NotTag> {
+    let bytes = s.as_bytes();
+    let length = bytes.len();
+
+    debug_assert_eq!(b'[', bytes[0]);
+
+    let mut p = 1;
+
+    let end = bytes.get(p) == Some(&b'/');
+
+    if end {
+        p += 1;
+    }
+
+    // name
+    let name_start = p;
+
+    while p < length {
+        match bytes[p] {
+            b'[' | b']' | b'/' | b'<' | b'>' => break,
+            e if e.is_ascii_whitespace() => break,
+            _ => p += 1,
+        }
+    }
+
+    if p == length {
+        return Err(NotTag::Incomplete);
+    }
+
+    let name = &s[name_start..p];
+
+    if !engine.is_shortcode_name(name) {
+        return Err(NotTag::Invalid);
+    }
+
+    // attributes
+    match bytes[p..].iter().position(|&e| e == b']' || e == b'[') {
+        Some(i) if bytes[p + i] == b']' => Ok(Shortcode {
+            raw: &s[..=(p + i)],
+            name,
+            end,
+        }),
+        Some(_) => Err(NotTag::Invalid),
+        None => Err(NotTag::Incomplete),
+    }
+}
+
+/// Find the `-->` of the comment at the beginning of `s`, and return the length of the comment. `<!-->` and `<!--->` are also comments.
+#[inline]
+pub(crate) fn find_comment_end(s: &str) -> Option<usize> {
+    s[2..].find("-->").map(|i| i + 5)
+}
+
+/// Find `</name\s*>` in `s` case-insensitively, and return its position and its length.
+fn find_end_tag(s: &str, name: &str) -> Option<(usize, usize)> {
+    let bytes = s.as_bytes();
+    let name_length = name.len();
+
+    let mut offset = 0;
+
+    while let Some(i) = s[offset..].find("</") {
+        let start = offset + i;
+        let name_start = start + 2;
+        let name_end = name_start + name_length;
+
+        if name_end <= bytes.len()
+            && bytes[name_start..name_end].eq_ignore_ascii_case(name.as_bytes())
+        {
+            let mut p = name_end;
+
+            while p < bytes.len() && bytes[p].is_ascii_whitespace() {
+                p += 1;
+            }
+
+            if bytes.get(p) == Some(&b'>') {
+                return Some((start, p + 1 - start));
+            }
+        }
+
+        offset = name_start;
+    }
+
+    None
+}
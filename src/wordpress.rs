@@ -0,0 +1,867 @@
+/// The block-level elements in the regular expressions of `wpautop`. In most of them, a tag name only needs to start with one of these names.
+const ALL_BLOCKS: [&str; 47] = [
+    "table",
+    "thead",
+    "tfoot",
+    "caption",
+    "col",
+    "colgroup",
+    "tbody",
+    "tr",
+    "td",
+    "th",
+    "div",
+    "dl",
+    "dd",
+    "dt",
+    "ul",
+    "ol",
+    "li",
+    "pre",
+    "form",
+    "map",
+    "area",
+    "blockquote",
+    "address",
+    "math",
+    "style",
+    "p",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "fieldset",
+    "legend",
+    "section",
+    "article",
+    "aside",
+    "hgroup",
+    "header",
+    "footer",
+    "nav",
+    "figure",
+    "figcaption",
+    "details",
+    "menu",
+    "summary",
+];
+
+/// The block-level elements before which a `<br />` is removed.
+const BR_REMOVING_BLOCKS: [&str; 11] =
+    ["p", "li", "div", "dl", "dd", "dt", "th", "pre", "td", "ul", "ol"];
+
+/// The elements whose newlines are not turned into `<br />`.
+const NEWLINE_PRESERVING_ELEMENTS: [&str; 3] = ["script", "style", "svg"];
+
+const NEWLINE_PLACEHOLDER: &str = " <!-- wpnl --> ";
+
+const PRE_PLACEHOLDER_PREFIX: &str = "<pre wp-pre-tag-";
+
+/// Identify text formatted with newlines and replace double line-breaks with HTML paragraph tags, exactly like `wpautop($text, $br)`. It runs the same replaces over the whole text in the same order, and every regular expression is matched by hand like PCRE does, so even the quirks on malformed HTML are kept.
+pub(crate) fn wpautop(text: &str, br: bool) -> String {
+    if text.trim_matches(is_php_trim_char).is_empty() {
+        return String::new();
+    }
+
+    // just to make things a little easier, pad the end
+    let mut text = format!("{}\n", text);
+
+    // `<pre>` elements are replaced with placeholders and brought back after `auto_p`
+    let mut pre_tags: Vec<(String, String)> = Vec::new();
+
+    if text.contains("<pre") {
+        text = replace_pre_tags(&text, &mut pre_tags);
+    }
+
+    // change multiple `<br>`s into two line breaks, which will turn into paragraphs
+    let text = replace_all(&text, |text, i, out| {
+        let end = br_tag_end(text, i)?;
+        let end = br_tag_end(text, skip_whitespace(text, end))?;
+
+        out.push_str("\n\n");
+
+        Some(end)
+    });
+
+    // add a double line break above block-level opening tags
+    let text = replace_all(&text, |text, i, out| {
+        let bytes = text.as_bytes();
+
+        // the longest name has 10 characters
+        let length = bytes[(i + 1)..]
+            .iter()
+            .take(11)
+            .position(|&e| is_whitespace(e) || e == b'/' || e == b'>')?;
+
+        let end = i + 1 + length;
+
+        if !ALL_BLOCKS.contains(&&text[(i + 1)..end]) {
+            return None;
+        }
+
+        out.push_str("\n\n");
+        out.push_str(&text[i..=end]);
+
+        Some(end + 1)
+    });
+
+    // add a double line break below block-level closing tags
+    let text = replace_all(&text, |text, i, out| {
+        let bytes = text.as_bytes();
+
+        if bytes.get(i + 1) != Some(&b'/') {
+            return None;
+        }
+
+        let length = bytes[(i + 2)..].iter().take(11).position(|&e| e == b'>')?;
+        let end = i + 2 + length;
+
+        if !ALL_BLOCKS.contains(&&text[(i + 2)..end]) {
+            return None;
+        }
+
+        out.push_str(&text[i..=end]);
+        out.push_str("\n\n");
+
+        Some(end + 1)
+    });
+
+    // add a double line break after `<hr>` tags, which are self closing
+    let text = replace_all(&text, |text, i, out| {
+        if !text[i..].starts_with("<hr") {
+            return None;
+        }
+
+        let end = tag_end_after_whitespace(text, i + 3)?;
+
+        out.push_str(&text[i..end]);
+        out.push_str("\n\n");
+
+        Some(end)
+    });
+
+    // standardize newline characters to `"\n"`
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+
+    // find newlines in all elements and add placeholders
+    let mut text = replace_newlines_in_tags(&text);
+
+    // collapse line breaks before and after `<option>` elements so they don't get `auto_p`ed
+    if text.contains("<option") {
+        text = collapse_whitespace(&text, true, false, |text, i| {
+            text[i..].starts_with("<option").then(|| i + 7)
+        });
+        text = collapse_whitespace(&text, false, true, |text, i| {
+            text[i..].starts_with("</option>").then(|| i + 9)
+        });
+    }
+
+    // collapse line breaks inside `<object>` elements, before `<param>` and `<embed>` elements
+    if text.contains("</object>") {
+        let mut gt = Finder::new(">");
+
+        text = collapse_whitespace(&text, false, true, |text, i| {
+            if text[i..].starts_with("<object") {
+                gt.find(text, i + 7).map(|e| e + 1)
+            } else {
+                None
+            }
+        });
+        text = collapse_whitespace(&text, true, false, |text, i| {
+            text[i..].starts_with("</object>").then(|| i + 9)
+        });
+
+        let mut gt = Finder::new(">");
+
+        text = collapse_whitespace(&text, true, true, |text, i| {
+            let start = start_tag_name(text, i, true)?;
+
+            if text[start..].starts_with("param") || text[start..].starts_with("embed") {
+                gt.find(text, start + 5).map(|e| e + 1)
+            } else {
+                None
+            }
+        });
+    }
+
+    // collapse line breaks inside `<audio>` and `<video>` elements, before and after `<source>` and `<track>` elements
+    if text.contains("<source") || text.contains("<track") {
+        let mut gt = Finder::new(">");
+        let mut bracket = Finder::new("]");
+
+        text = collapse_whitespace(&text, false, true, |text, i| {
+            if !matches!(text.as_bytes()[i], b'<' | b'[') {
+                return None;
+            }
+
+            if !text[(i + 1)..].starts_with("audio") && !text[(i + 1)..].starts_with("video") {
+                return None;
+            }
+
+            let end = match (gt.find(text, i + 6), bracket.find(text, i + 6)) {
+                (Some(a), Some(b)) => a.min(b),
+                (a, b) => a.or(b)?,
+            };
+
+            Some(end + 1)
+        });
+        text = collapse_whitespace(&text, true, false, |text, i| {
+            let bytes = text.as_bytes();
+
+            if !matches!(bytes[i], b'<' | b'[') || bytes.get(i + 1) != Some(&b'/') {
+                return None;
+            }
+
+            if !text[(i + 2)..].starts_with("audio") && !text[(i + 2)..].starts_with("video") {
+                return None;
+            }
+
+            matches!(bytes.get(i + 7), Some(b'>' | b']')).then(|| i + 8)
+        });
+
+        let mut gt = Finder::new(">");
+
+        text = collapse_whitespace(&text, true, true, |text, i| {
+            let start = start_tag_name(text, i, false)?;
+
+            if text[start..].starts_with("source") || text[start..].starts_with("track") {
+                gt.find(text, start + 5).map(|e| e + 1)
+            } else {
+                None
+            }
+        });
+    }
+
+    // collapse line breaks before and after `<figcaption>` elements
+    if text.contains("<figcaption") {
+        let mut gt = Finder::new(">");
+
+        text = collapse_whitespace(&text, true, false, |text, i| {
+            if text[i..].starts_with("<figcaption") {
+                gt.find(text, i + 11).map(|e| e + 1)
+            } else {
+                None
+            }
+        });
+        text = collapse_whitespace(&text, false, true, |text, i| {
+            text[i..].starts_with("</figcaption>").then(|| i + 13)
+        });
+    }
+
+    // remove more than two contiguous line breaks, split up the contents separated by double line breaks, and wrap every bit with a `<p>`
+    let text = wrap_paragraphs(&collapse_newlines(&text));
+
+    // under certain strange conditions it could create a `<p>` of entirely whitespace
+    let text = replace_all(&text, |text, i, _| {
+        if !text[i..].starts_with("<p>") {
+            return None;
+        }
+
+        let end = skip_whitespace(text, i + 3);
+
+        text[end..].starts_with("</p>").then(|| end + 4)
+    });
+
+    // add a closing `</p>` inside `<div>`, `<address>`, or `<form>` tag if missing
+    let mut lt = Finder::new("<");
+
+    let text = replace_all(&text, |text, i, out| {
+        if !text[i..].starts_with("<p>") {
+            return None;
+        }
+
+        let end = lt.find(text, i + 3)?;
+
+        if end == i + 3 {
+            return None;
+        }
+
+        let length = ["</div>", "</address>", "</form>"]
+            .iter()
+            .find(|e| text[end..].starts_with(*e))
+            .map(|e| e.len())?;
+
+        out.push_str(&text[i..end]);
+        out.push_str("</p>");
+        out.push_str(&text[end..(end + length)]);
+
+        Some(end + length)
+    });
+
+    // if an opening or closing block element tag is wrapped in a `<p>`, unwrap it
+    let mut gt = Finder::new(">");
+
+    let text = replace_all(&text, |text, i, out| {
+        if !text[i..].starts_with("<p>") {
+            return None;
+        }
+
+        let start = skip_whitespace(text, i + 3);
+        let end = block_tag_end(text, start, &ALL_BLOCKS, &mut gt)?;
+        let p_end = skip_whitespace(text, end);
+
+        if !text[p_end..].starts_with("</p>") {
+            return None;
+        }
+
+        out.push_str(&text[start..end]);
+
+        Some(p_end + 4)
+    });
+
+    // in some cases `<li>` may get wrapped in `<p>`, fix them
+    let mut p_end = Finder::new("</p>");
+    let mut newline = Finder::new("\n");
+
+    let text = replace_all(&text, |text, i, out| {
+        if !text[i..].starts_with("<p><li") {
+            return None;
+        }
+
+        // `.+?` does not match newlines
+        let end = p_end.find(text, i + 7)?;
+
+        if newline.find(text, i + 6).map_or(false, |e| e < end) {
+            return None;
+        }
+
+        out.push_str(&text[(i + 3)..end]);
+
+        Some(end + 4)
+    });
+
+    // if a `<blockquote>` is wrapped with a `<p>`, move it inside the `<blockquote>`
+    let mut gt = Finder::new(">");
+
+    let text = replace_all(&text, |text, i, out| {
+        let prefix = "<p><blockquote";
+
+        if !text.as_bytes()[i..]
+            .get(..prefix.len())
+            .map_or(false, |e| e.eq_ignore_ascii_case(prefix.as_bytes()))
+        {
+            return None;
+        }
+
+        let end = gt.find(text, i + prefix.len())?;
+
+        out.push_str("<blockquote");
+        out.push_str(&text[(i + prefix.len())..end]);
+        out.push_str("><p>");
+
+        Some(end + 1)
+    });
+    let text = text.replace("</blockquote></p>", "</p></blockquote>");
+
+    // if an opening or closing block element tag is preceded by an opening `<p>` tag, remove it
+    let mut gt = Finder::new(">");
+
+    let text = replace_all(&text, |text, i, out| {
+        if !text[i..].starts_with("<p>") {
+            return None;
+        }
+
+        let start = skip_whitespace(text, i + 3);
+        let end = block_tag_end(text, start, &ALL_BLOCKS, &mut gt)?;
+
+        out.push_str(&text[start..end]);
+
+        Some(end)
+    });
+
+    // if an opening or closing block element tag is followed by a closing `</p>` tag, remove it
+    let mut gt = Finder::new(">");
+
+    let mut text = replace_all(&text, |text, i, out| {
+        let end = block_tag_end(text, i, &ALL_BLOCKS, &mut gt)?;
+        let p_end = skip_whitespace(text, end);
+
+        if !text[p_end..].starts_with("</p>") {
+            return None;
+        }
+
+        out.push_str(&text[i..end]);
+
+        Some(p_end + 4)
+    });
+
+    // optionally insert line breaks
+    if br {
+        text = replace_newlines_with_br(&text);
+    }
+
+    // if a `<br />` tag is after an opening or closing block tag, remove it
+    let mut gt = Finder::new(">");
+
+    let text = replace_all(&text, |text, i, out| {
+        let end = block_tag_end(text, i, &ALL_BLOCKS, &mut gt)?;
+        let br_end = skip_whitespace(text, end);
+
+        if !text[br_end..].starts_with("<br />") {
+            return None;
+        }
+
+        out.push_str(&text[i..end]);
+
+        Some(br_end + 6)
+    });
+
+    // if a `<br />` tag is before a subset of opening or closing block tags, remove it
+    let mut gt = Finder::new(">");
+
+    let mut text = replace_all(&text, |text, i, out| {
+        if !text[i..].starts_with("<br />") {
+            return None;
+        }
+
+        let start = skip_whitespace(text, i + 6);
+        let end = block_tag_end(text, start, &BR_REMOVING_BLOCKS, &mut gt)?;
+
+        out.push_str(&text[(i + 6)..end]);
+
+        Some(end)
+    });
+
+    // `|\n</p>$|`, where `$` also matches before the last newline
+    if let Some(index) = text.rfind("\n</p>") {
+        if text.len() == index + 5 || &text[(index + 5)..] == "\n" {
+            text.remove(index);
+        }
+    }
+
+    // replace placeholder `<pre>` tags with their original content
+    if !pre_tags.is_empty() {
+        text = restore_pre_tags(text, &pre_tags);
+    }
+
+    // restore newlines in all elements
+    if text.contains(NEWLINE_PLACEHOLDER.trim()) {
+        text = text.replace(NEWLINE_PLACEHOLDER, "\n").replace(NEWLINE_PLACEHOLDER.trim(), "\n");
+    }
+
+    text
+}
+
+/// Whether `c` is stripped by `trim` in PHP.
+#[inline]
+fn is_php_trim_char(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\0' | '\x0B')
+}
+
+/// Whether `c` matches `\s` in PCRE.
+#[inline]
+fn is_whitespace(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\n' | b'\x0B' | b'\x0C' | b'\r')
+}
+
+/// The position after the whitespace starting at `i`.
+#[inline]
+fn skip_whitespace(text: &str, i: usize) -> usize {
+    i + text.as_bytes()[i..].iter().take_while(|&&e| is_whitespace(e)).count()
+}
+
+/// Finds `pattern` in a text from positions which usually grow, reusing the last result, so that a pass over the text stays linear.
+struct Finder {
+    pattern: &'static str,
+    /// The position the last search started from, and the position found.
+    last:    Option<(usize, Option<usize>)>,
+}
+
+impl Finder {
+    #[inline]
+    fn new(pattern: &'static str) -> Self {
+        Finder {
+            pattern,
+            last: None,
+        }
+    }
+
+    fn find(&mut self, text: &str, from: usize) -> Option<usize> {
+        if let Some((last_from, found)) = self.last {
+            if last_from <= from && found.map_or(true, |e| e >= from) {
+                return found;
+            }
+        }
+
+        let pattern = self.pattern.as_bytes();
+
+        let found = text
+            .as_bytes()
+            .get(from..)
+            .unwrap_or_default()
+            .windows(pattern.len())
+            .position(|e| e == pattern)
+            .map(|e| from + e);
+
+        self.last = Some((from, found));
+
+        found
+    }
+}
+
+/// Replace the matches of a pattern in `text`, like `preg_replace`. `f` is called at every `<`, and if the pattern matches there, it writes the replacement to `out` and returns the end of the match.
+fn replace_all<F: FnMut(&str, usize, &mut String) -> Option<usize>>(
+    text: &str,
+    mut f: F,
+) -> String {
+    let bytes = text.as_bytes();
+
+    let mut out = String::with_capacity(text.len() + (text.len() >> 3));
+    let mut written = 0;
+    let mut i = 0;
+
+    while let Some(index) = bytes[i..].iter().position(|&e| e == b'<') {
+        let start = i + index;
+
+        out.push_str(&text[written..start]);
+        written = start;
+
+        match f(text, start, &mut out) {
+            Some(end) => {
+                written = end;
+                i = end;
+            },
+            None => i = start + 1,
+        }
+    }
+
+    out.push_str(&text[written..]);
+
+    out
+}
+
+/// Remove the whitespace before (`\s*X`) and/or after (`X\s*`) every match of a pattern in `text`. `f` returns the end of the match if the pattern matches at the given position.
+fn collapse_whitespace<F: FnMut(&str, usize) -> Option<usize>>(
+    text: &str,
+    before: bool,
+    after: bool,
+    mut f: F,
+) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut written = 0;
+    let mut i = 0;
+
+    while i < text.len() {
+        let start = if before { skip_whitespace(text, i) } else { i };
+
+        if start == text.len() {
+            break;
+        }
+
+        match f(text, start) {
+            Some(end) => {
+                out.push_str(&text[written..i]);
+                out.push_str(&text[start..end]);
+
+                i = if after { skip_whitespace(text, end) } else { end };
+                written = i;
+            },
+            // none of the whitespace can start a match
+            None if start > i => i = start,
+            None => i += text[i..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+
+    out.push_str(&text[written..]);
+
+    out
+}
+
+/// The end of `<br\s*/?>` at `i`.
+fn br_tag_end(text: &str, i: usize) -> Option<usize> {
+    if !text[i..].starts_with("<br") {
+        return None;
+    }
+
+    tag_end_after_whitespace(text, i + 3)
+}
+
+/// The end of `\s*/?>` at `i`.
+fn tag_end_after_whitespace(text: &str, i: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+
+    let mut end = skip_whitespace(text, i);
+
+    if bytes.get(end) == Some(&b'/') {
+        end += 1;
+    }
+
+    (bytes.get(end) == Some(&b'>')).then(|| end + 1)
+}
+
+/// The position of the tag name of `<` (or `</` if `end_tag` is true) at `i`.
+#[inline]
+fn start_tag_name(text: &str, i: usize, end_tag: bool) -> Option<usize> {
+    let bytes = text.as_bytes();
+
+    if bytes.get(i) != Some(&b'<') {
+        return None;
+    }
+
+    if end_tag && bytes.get(i + 1) == Some(&b'/') {
+        Some(i + 2)
+    } else {
+        Some(i + 1)
+    }
+}
+
+/// The end of `</?(?:names)[^>]*>` at `i`.
+fn block_tag_end(text: &str, i: usize, names: &[&str], gt: &mut Finder) -> Option<usize> {
+    let start = start_tag_name(text, i, true)?;
+
+    if !names.iter().any(|e| text[start..].starts_with(e)) {
+        return None;
+    }
+
+    gt.find(text, start).map(|e| e + 1)
+}
+
+/// Replace every `<pre>` element with a placeholder like `<pre wp-pre-tag-0></pre>`, and keep the element in `pre_tags`. The text is split by `</pre>`, so a `</pre>` without `<pre` before it is removed.
+fn replace_pre_tags(text: &str, pre_tags: &mut Vec<(String, String)>) -> String {
+    let mut parts: Vec<&str> = text.split("</pre>").collect();
+    let last_part = parts.pop().unwrap();
+
+    let mut out = String::with_capacity(text.len());
+
+    for part in parts {
+        match part.find("<pre") {
+            Some(start) => {
+                let name = format!("{}{}></pre>", PRE_PLACEHOLDER_PREFIX, pre_tags.len());
+
+                out.push_str(&part[..start]);
+                out.push_str(&name);
+
+                pre_tags.push((name, format!("{}</pre>", &part[start..])));
+            },
+            // malformed HTML
+            None => out.push_str(part),
+        }
+    }
+
+    out.push_str(last_part);
+
+    out
+}
+
+/// Replace the placeholders of `<pre>` elements with them, one after another like `str_replace` with arrays.
+fn restore_pre_tags(text: String, pre_tags: &[(String, String)]) -> String {
+    // a `<pre>` element can contain a placeholder only if the input does
+    if pre_tags.iter().any(|(_, pre)| pre.contains(PRE_PLACEHOLDER_PREFIX)) {
+        return pre_tags.iter().fold(text, |text, (name, pre)| text.replace(name.as_str(), pre));
+    }
+
+    let mut out =
+        String::with_capacity(text.len() + pre_tags.iter().map(|e| e.1.len()).sum::<usize>());
+    let mut rest = text.as_str();
+
+    while let Some(index) = rest.find(PRE_PLACEHOLDER_PREFIX) {
+        let after = &rest[(index + PRE_PLACEHOLDER_PREFIX.len())..];
+        let digits = after.bytes().take_while(u8::is_ascii_digit).count();
+
+        let pre = after[..digits]
+            .parse::<usize>()
+            .ok()
+            .and_then(|e| pre_tags.get(e))
+            .filter(|(name, _)| rest[index..].starts_with(name.as_str()));
+
+        match pre {
+            Some((name, pre)) => {
+                out.push_str(&rest[..index]);
+                out.push_str(pre);
+
+                rest = &rest[(index + name.len())..];
+            },
+            None => {
+                let end = index + PRE_PLACEHOLDER_PREFIX.len();
+
+                out.push_str(&rest[..end]);
+
+                rest = &rest[end..];
+            },
+        }
+    }
+
+    out.push_str(rest);
+
+    out
+}
+
+/// Replace the newlines in tags and comments with placeholders, like `wp_replace_in_html_tags`. The elements are found like `wp_html_split`.
+fn replace_newlines_in_tags(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + (text.len() >> 3));
+    let mut i = 0;
+
+    while let Some(index) = text[i..].find('<') {
+        let start = i + index;
+        let end = html_element_end(text, start);
+
+        out.push_str(&text[i..start]);
+        out.push_str(&text[start..end].replace('\n', NEWLINE_PLACEHOLDER));
+
+        i = end;
+    }
+
+    out.push_str(&text[i..]);
+
+    out
+}
+
+/// The end of the element (a tag, a comment or a CDATA section) starting at `start`. An unclosed comment or CDATA section lasts until the end.
+fn html_element_end(text: &str, start: usize) -> usize {
+    let rest = &text[(start + 1)..];
+    let bytes = rest.as_bytes();
+
+    let length = if rest.starts_with("!--") {
+        // `!(?:-(?!->)[^\-]*+)*+(?:-->)?`
+        let mut j = 1;
+
+        while bytes.get(j) == Some(&b'-') && !rest[(j + 1)..].starts_with("->") {
+            j += 1 + bytes[(j + 1)..].iter().take_while(|&&e| e != b'-').count();
+        }
+
+        if rest[j..].starts_with("-->") {
+            j += 3;
+        }
+
+        j
+    } else if rest.starts_with("![CDATA[") {
+        // `!\[CDATA\[[^\]]*+(?:](?!]>)[^\]]*+)*+(?:]]>)?`
+        let mut j = 8 + bytes[8..].iter().take_while(|&&e| e != b']').count();
+
+        while bytes.get(j) == Some(&b']') && !rest[(j + 1)..].starts_with("]>") {
+            j += 1 + bytes[(j + 1)..].iter().take_while(|&&e| e != b']').count();
+        }
+
+        if rest[j..].starts_with("]]>") {
+            j += 3;
+        }
+
+        j
+    } else {
+        // `[^>]*>?`
+        rest.find('>').map_or(rest.len(), |e| e + 1)
+    };
+
+    start + 1 + length
+}
+
+/// Replace `\n\n+` with `\n\n`.
+fn collapse_newlines(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(index) = rest.find("\n\n") {
+        let end = index + 2 + rest[(index + 2)..].bytes().take_while(|&e| e == b'\n').count();
+
+        out.push_str(&rest[..(index + 2)]);
+
+        rest = &rest[end..];
+    }
+
+    out.push_str(rest);
+
+    out
+}
+
+/// Split `text` by `\n\s*\n`, and wrap every non-empty part, with newlines trimmed, in a `<p>`.
+fn wrap_paragraphs(text: &str) -> String {
+    let bytes = text.as_bytes();
+
+    let mut out = String::with_capacity(text.len() + (text.len() >> 3));
+    let mut start = 0;
+    let mut i = 0;
+
+    let mut push = |part: &str| {
+        if !part.is_empty() {
+            out.push_str("<p>");
+            out.push_str(part.trim_matches('\n'));
+            out.push_str("</p>\n");
+        }
+    };
+
+    while i < bytes.len() {
+        if bytes[i] != b'\n' {
+            i += 1;
+
+            continue;
+        }
+
+        let end = skip_whitespace(text, i + 1);
+
+        // `\s*` backtracks to the last newline
+        match bytes[(i + 1)..end].iter().rposition(|&e| e == b'\n') {
+            Some(index) => {
+                push(&text[start..i]);
+
+                start = i + 1 + index + 1;
+                i = start;
+            },
+            None => i = end,
+        }
+    }
+
+    push(&text[start..]);
+
+    out
+}
+
+/// Turn the newlines which are not preceded by `<br />` into `<br />`, except in `<script>`, `<style>` and `<svg>` elements.
+fn replace_newlines_with_br(text: &str) -> String {
+    const PRESERVED_NEWLINE: &str = "<WPPreserveNewline />";
+
+    let mut closings = [Finder::new("</script>"), Finder::new("</style>"), Finder::new("</svg>")];
+
+    // replace newlines that shouldn't be touched with a placeholder
+    let text = replace_all(text, |text, i, out| {
+        let index =
+            NEWLINE_PRESERVING_ELEMENTS.iter().position(|e| text[(i + 1)..].starts_with(e))?;
+
+        let closing = &mut closings[index];
+        let end = closing.find(text, i + 1 + NEWLINE_PRESERVING_ELEMENTS[index].len())?
+            + closing.pattern.len();
+
+        out.push_str(&text[i..end].replace('\n', PRESERVED_NEWLINE));
+
+        Some(end)
+    });
+
+    // normalize `<br>`
+    let text = text.replace("<br>", "<br />").replace("<br/>", "<br />");
+
+    // replace any new line characters that aren't preceded by a `<br />` with a `<br />`, like `(?<!<br />)\s*\n`
+    let bytes = text.as_bytes();
+
+    let mut out = String::with_capacity(text.len() + (text.len() >> 2));
+    let mut written = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if !is_whitespace(bytes[i]) || bytes[..i].ends_with(b"<br />") {
+            i += 1;
+
+            continue;
+        }
+
+        let end = skip_whitespace(&text, i);
+
+        // `\s*` backtracks to the last newline
+        match bytes[i..end].iter().rposition(|&e| e == b'\n') {
+            Some(index) => {
+                out.push_str(&text[written..i]);
+                out.push_str("<br />\n");
+
+                i += index + 1;
+                written = i;
+            },
+            None => i = end,
+        }
+    }
+
+    out.push_str(&text[written..]);
+
+    // replace newline placeholders with newlines
+    out.replace(PRESERVED_NEWLINE, "\n")
+}
@@ -0,0 +1,253 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Write},
+};
+
+use crate::{
+    tag_names::is_void_tag_name,
+    tokenizer::{find_comment_end, parse_tag, Tag},
+};
+
+/// Write `s` with the named character references converted into numeric ones, except for `&amp;`, `&lt;`, `&gt;`, `&quot;` and `&apos;`. An `&` which does not start a known character reference is escaped. The characters which are not allowed in XML, such as control characters and `&#0;`, are replaced with U+FFFD.
+pub(crate) fn write_with_numeric_references<W: ?Sized + Write>(
+    out: &mut W,
+    s: &str,
+) -> fmt::Result {
+    let mut rest = s;
+
+    while let Some(index) = rest.find(|c| c == '&' || !is_xml_char(c)) {
+        out.write_str(&rest[..index])?;
+
+        rest = &rest[index..];
+
+        if !rest.starts_with('&') {
+            out.write_char(char::REPLACEMENT_CHARACTER)?;
+
+            rest = &rest[rest.chars().next().unwrap().len_utf8()..];
+
+            continue;
+        }
+
+        match reference_length(rest) {
+            Some(length) => {
+                let reference = &rest[..length];
+
+                if is_numeric_or_predefined(reference) {
+                    match numeric_reference_value(reference) {
+                        Some(c) if !is_xml_char(c) => out.write_str("&#xFFFD;")?,
+                        _ => out.write_str(reference)?,
+                    }
+                } else {
+                    for c in html_escape::decode_html_entities(reference).chars() {
+                        write!(out, "&#{};", c as u32)?;
+                    }
+                }
+
+                rest = &rest[length..];
+            },
+            None => {
+                out.write_str("&amp;")?;
+
+                rest = &rest[1..];
+            },
+        }
+    }
+
+    out.write_str(rest)
+}
+
+/// The character of a numeric character reference, like `&#38;` and `&#x26;`. A code point which is not a character, such as a surrogate, is `U+0000`. It is `None` for a named character reference.
+fn numeric_reference_value(reference: &str) -> Option<char> {
+    let number = reference.strip_prefix("&#")?.strip_suffix(';')?;
+
+    let value = match number.strip_prefix(&['x', 'X'][..]) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => number.parse(),
+    };
+
+    Some(value.ok().and_then(char::from_u32).unwrap_or('\0'))
+}
+
+/// Whether `c` matches the `Char` production of XML, which leaves out most control characters, surrogates, U+FFFE and U+FFFF.
+#[inline]
+fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..)
+}
+
+/// The length of the character reference at the beginning of `s` if it is a valid numeric one or a known named one.
+fn reference_length(s: &str) -> Option<usize> {
+    let end = s.as_bytes().iter().take(34).position(|e| *e == b';')?;
+    let name = &s[1..end];
+
+    let valid =
+        if let Some(hex) = name.strip_prefix('#').and_then(|e| e.strip_prefix(&['x', 'X'][..])) {
+            !hex.is_empty() && hex.bytes().all(|e| e.is_ascii_hexdigit())
+        } else if let Some(decimal) = name.strip_prefix('#') {
+            !decimal.is_empty() && decimal.bytes().all(|e| e.is_ascii_digit())
+        } else {
+            !name.is_empty()
+                && name.bytes().all(|e| e.is_ascii_alphanumeric())
+                && html_escape::decode_html_entities(&s[..=end]) != s[..=end]
+        };
+
+    if valid {
+        Some(end + 1)
+    } else {
+        None
+    }
+}
+
+#[inline]
+fn is_numeric_or_predefined(reference: &str) -> bool {
+    reference.starts_with("&#")
+        || matches!(reference, "&amp;" | "&lt;" | "&gt;" | "&quot;" | "&apos;")
+}
+
+/// Write text with the character references converted like `write_with_numeric_references`, and with `<` and `>` escaped.
+#[inline]
+pub(crate) fn write_xhtml_text<W: ?Sized + Write>(out: &mut W, s: &str) -> fmt::Result {
+    write_escaped(out, s, &['<', '>'])
+}
+
+/// Write `tag` as a well-formed XML tag. The tag name is lowercased, every attribute value is double-quoted, a boolean attribute (such as `disabled`) gets its name as the value, an attribute which is not a valid XML name or is repeated is left out, and a start tag of a void element ends with ` />`. The character references are converted like `write_with_numeric_references`. A tag whose name is not a valid XML name is written as text.
+pub(crate) fn write_xhtml_tag<W: ?Sized + Write>(out: &mut W, tag: &Tag) -> fmt::Result {
+    let name = tag.name.to_ascii_lowercase();
+
+    if !is_xml_name(&name) {
+        return write_xhtml_text(out, tag.raw);
+    }
+
+    if tag.end {
+        return write!(out, "</{}>", name);
+    }
+
+    write!(out, "<{}", name)?;
+
+    let mut names = HashSet::new();
+
+    for (attribute_name, value) in tag.attributes() {
+        if !is_xml_name(attribute_name) || !names.insert(attribute_name.to_ascii_lowercase()) {
+            continue;
+        }
+
+        write!(out, " {}=\"", attribute_name)?;
+        write_escaped(out, value.unwrap_or(attribute_name), &['<', '"'])?;
+        out.write_char('"')?;
+    }
+
+    if tag.self_closing || is_void_tag_name(&name) {
+        out.write_str(" />")
+    } else {
+        out.write_char('>')
+    }
+}
+
+/// Write a comment as a well-formed XML comment. `--` in the comment is split by a space, and so is the `-` at its end from `-->`. An unclosed comment is closed, `<!-->` and `<!--->` become empty comments, and the characters which are not allowed in XML are replaced with U+FFFD.
+pub(crate) fn write_xhtml_comment<W: ?Sized + Write>(out: &mut W, raw: &str) -> fmt::Result {
+    let content = &raw[4..];
+
+    let content = match content.strip_suffix("-->") {
+        Some(content) => content,
+        // `<!-->` and `<!--->`
+        None if content == ">" || content == "->" => "",
+        None => content,
+    };
+
+    out.write_str("<!--")?;
+
+    let mut last = None;
+
+    for c in content.chars() {
+        if c == '-' && last == Some('-') {
+            out.write_char(' ')?;
+        }
+
+        out.write_char(if is_xml_char(c) { c } else { char::REPLACEMENT_CHARACTER })?;
+
+        last = Some(c);
+    }
+
+    if last == Some('-') {
+        out.write_char(' ')?;
+    }
+
+    out.write_str("-->")
+}
+
+/// Write the inner HTML of a preserved element whose content is parsed as HTML (such as `<pre>`), with its tags written like `write_xhtml_tag`, its comments written like `write_xhtml_comment` and its text written like `write_xhtml_text`.
+pub(crate) fn write_xhtml_inner_html<W: ?Sized + Write>(out: &mut W, html: &str) -> fmt::Result {
+    let mut rest = html;
+
+    while let Some(index) = rest.find('<') {
+        write_xhtml_text(out, &rest[..index])?;
+
+        rest = &rest[index..];
+
+        if rest.starts_with("<!--") {
+            if let Some(length) = find_comment_end(rest) {
+                write_xhtml_comment(out, &rest[..length])?;
+
+                rest = &rest[length..];
+
+                continue;
+            }
+        } else if let Ok(tag) = parse_tag(rest) {
+            write_xhtml_tag(out, &tag)?;
+
+            rest = &rest[tag.raw.len()..];
+
+            continue;
+        }
+
+        out.write_str("&lt;")?;
+
+        rest = &rest[1..];
+    }
+
+    write_xhtml_text(out, rest)
+}
+
+/// Write `s` like `write_with_numeric_references`, with the `escaped` characters (`<`, `>` or `"`) escaped.
+fn write_escaped<W: ?Sized + Write>(out: &mut W, s: &str, escaped: &[char]) -> fmt::Result {
+    let mut rest = s;
+
+    while let Some(index) = rest.find(escaped) {
+        write_with_numeric_references(out, &rest[..index])?;
+
+        out.write_str(match rest.as_bytes()[index] {
+            b'<' => "&lt;",
+            b'>' => "&gt;",
+            _ => "&quot;",
+        })?;
+
+        rest = &rest[(index + 1)..];
+    }
+
+    write_with_numeric_references(out, rest)
+}
+
+/// Whether `name` is a valid XML name.
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    match chars.next() {
+        Some(c) if is_xml_name_start_char(c) => (),
+        _ => return false,
+    }
+
+    chars.all(|c| {
+        is_xml_name_start_char(c)
+            || matches!(c, '-' | '.' | '0'..='9' | '\u{B7}' | '\u{300}'..='\u{36F}' | '\u{203F}'..='\u{2040}')
+    })
+}
+
+#[inline]
+fn is_xml_name_start_char(c: char) -> bool {
+    matches!(c,
+        ':' | 'A'..='Z' | '_' | 'a'..='z'
+        | '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}' | '\u{F8}'..='\u{2FF}'
+        | '\u{370}'..='\u{37D}' | '\u{37F}'..='\u{1FFF}' | '\u{200C}'..='\u{200D}'
+        | '\u{2070}'..='\u{218F}' | '\u{2C00}'..='\u{2FEF}' | '\u{3001}'..='\u{D7FF}'
+        | '\u{F900}'..='\u{FDCF}' | '\u{FDF0}'..='\u{FFFD}' | '\u{10000}'..='\u{EFFFF}'
+    )
+}
@@ -6,8 +6,25 @@ const DATA_FOLDER: &str = "data";
 
 #[test]
 fn test_files_in_the_data_folder() {
-    let data_folder = Path::new("tests").join(DATA_FOLDER);
+    test_files_in(
+        &Path::new("tests").join(DATA_FOLDER),
+        Options::new().br(true).esc_pre(true).remove_useless_newlines_in_pre(true),
+        false,
+    );
+}
 
+/// The `wpautop` test cases of WordPress, whose outputs are trimmed like in their assertions.
+#[test]
+fn test_files_in_the_wordpress_folder() {
+    test_files_in(
+        &Path::new("tests").join(DATA_FOLDER).join("wordpress"),
+        Options::wordpress_compat(),
+        true,
+    );
+}
+
+/// Compare the output of `auto_p` for every `*.test.html` file with its `*.autoped.html` file, whose surrounding whitespace is ignored. If `trim_output` is `true`, the surrounding whitespace of the output is ignored too.
+fn test_files_in(data_folder: &Path, options: Options, trim_output: bool) {
     for dir in data_folder.read_dir().unwrap().map(|dir| dir.unwrap()) {
         if dir.file_type().unwrap().is_file() {
             let file_path = dir.path();
@@ -23,17 +40,17 @@ fn test_files_in_the_data_folder() {
 
                 html_file.read_to_string(&mut html).unwrap();
 
-                let autoped_html = auto_p(
-                    html,
-                    Options::new().br(true).esc_pre(true).remove_useless_newlines_in_pre(true),
-                );
+                let autoped_html = auto_p(html, options.clone());
 
                 let mut autoped_file = File::open(autoped_file_path.as_path()).unwrap();
                 let mut autoped_file_content = String::new();
 
                 autoped_file.read_to_string(&mut autoped_file_content).unwrap();
 
-                if autoped_file_content.trim().ne(&autoped_html) {
+                let autoped_html =
+                    if trim_output { autoped_html.trim() } else { autoped_html.as_str() };
+
+                if autoped_file_content.trim().ne(autoped_html) {
                     eprintln!("{}", autoped_html);
                     panic!(
                         "The `auto_p`ed html does not match the `auto_p`ed file: {}",
@@ -0,0 +1,156 @@
+use html_auto_p::*;
+
+/// A xorshift generator, so that the generated input is the same on every run.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+
+        self.0
+    }
+
+    fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+        choices[(self.next() % choices.len() as u64) as usize]
+    }
+}
+
+const INLINES: &[&str] = &[
+    "a",
+    "b c",
+    "x &amp; y",
+    "é",
+    " ",
+    "\n",
+    "\n\n",
+    "\r\n",
+    "<br>",
+    "<br />\n",
+    "<img src=\"a\">",
+    "<a href=\"https://example.com\">link</a>",
+    "https://example.com",
+    "<!-- c -->",
+    "<!-- a\n\nb -->",
+    "[gallery]",
+    "<input type=\"text\">",
+];
+
+const PRESERVED: &[&str] = &[
+    "<pre>a\n\nb</pre>",
+    "<pre>\nx\n</pre>",
+    "<textarea>a\n\nb</textarea>",
+    "<style>a {}</style>",
+    "<svg>\n<path d=\"a\" />\n</svg>",
+    "<select>\n <option>a</option>\n <option>b</option>\n</select>",
+    "<!-- wp:paragraph -->\n<p>x</p>\n<!-- /wp:paragraph -->",
+    "<hr />",
+];
+
+const BLOCKS: &[&str] = &["div", "blockquote", "section", "figure", "my-box"];
+
+const INLINE_ELEMENTS: &[&str] = &["b", "span", "em"];
+
+/// Append well-formed HTML to `out`.
+fn generate(rng: &mut Rng, depth: usize, out: &mut String) {
+    for _ in 0..(1 + rng.next() % 5) {
+        match rng.next() % 10 {
+            0..=3 => out.push_str(rng.pick(INLINES)),
+            4 => out.push_str(rng.pick(PRESERVED)),
+            5 if depth > 0 => {
+                let name = rng.pick(INLINE_ELEMENTS);
+
+                out.push_str(&format!("<{}>{}</{}>", name, rng.pick(INLINES), name));
+            },
+            6 if depth > 0 => {
+                let name = rng.pick(BLOCKS);
+
+                out.push('<');
+                out.push_str(name);
+                out.push('>');
+                out.push_str(rng.pick(&["", "\n", "\n\n"]));
+                generate(rng, depth - 1, out);
+                out.push_str(rng.pick(&["", "\n", "\n\n"]));
+                out.push_str("</");
+                out.push_str(name);
+                out.push('>');
+            },
+            7 if depth > 0 => {
+                out.push_str("<ul>\n<li>");
+                generate(rng, depth - 1, out);
+                out.push_str("</li>\n<li>");
+                out.push_str(rng.pick(INLINES));
+                out.push_str("</li>\n</ul>");
+            },
+            8 if depth > 0 => {
+                out.push_str("<table><tr><td>");
+                generate(rng, depth - 1, out);
+                out.push_str("</td></tr></table>");
+            },
+            9 if depth > 0 => {
+                out.push_str(rng.pick(&["<p>", "<p class=\"x\">"]));
+                out.push_str(rng.pick(INLINES));
+                out.push_str("</p>");
+            },
+            _ => out.push_str(rng.pick(&["\n\n", "\n"])),
+        }
+    }
+}
+
+#[test]
+fn auto_p_is_idempotent() {
+    let mut options = vec![
+        Options::new().remove_useless_newlines_in_pre(true),
+        Options::new().gutenberg(true),
+        Options::new().br(true).custom_elements(CustomElementPolicy::Block),
+    ];
+
+    // every layout with every paragraph element, with and without `xhtml` and `br`
+    for layout in [Options::new(), Options::new().pretty(2), Options::new().compact(true)] {
+        for paragraph in [
+            ParagraphElement::new(),
+            ParagraphElement::new().first_class("a"),
+            ParagraphElement::new().tag_name("div").first_class("a"),
+            ParagraphElement::new().tag_name("my-para"),
+        ] {
+            for xhtml in [false, true] {
+                for br in [false, true] {
+                    options.push(layout.clone().paragraph(paragraph.clone()).xhtml(xhtml).br(br));
+                }
+            }
+        }
+    }
+
+    let engines: Vec<AutoPEngine> = options
+        .iter()
+        .flat_map(|options| {
+            let builder = AutoPEngine::builder().options(options.clone());
+
+            [
+                builder.clone().build(),
+                builder.shortcodes(["gallery"]).linkify(LinkifyOptions::new()).build(),
+            ]
+        })
+        .collect();
+
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+
+    for _ in 0..1000 {
+        let mut html = String::new();
+
+        generate(&mut rng, 3, &mut html);
+
+        for engine in engines.iter() {
+            let once = engine.auto_p(html.as_str());
+
+            assert_eq!(
+                once,
+                engine.auto_p(once.as_str()),
+                "input: {:?}, engine: {:?}",
+                html,
+                engine
+            );
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    cell::RefCell,
+    io::{self, ErrorKind, Read, Write},
+    rc::Rc,
+};
+
+use html_auto_p::*;
+
+/// A reader which reads at most `chunk_size` bytes at a time.
+struct ChunkReader<'a> {
+    data:       &'a [u8],
+    chunk_size: usize,
+}
+
+impl<'a> Read for ChunkReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.data.len().min(buf.len()).min(self.chunk_size);
+
+        buf[..length].copy_from_slice(&self.data[..length]);
+
+        self.data = &self.data[length..];
+
+        Ok(length)
+    }
+}
+
+fn auto_p_stream_to_string(html: &str, chunk_size: usize, options: Options) -> String {
+    let mut output = Vec::new();
+
+    auto_p_stream(
+        ChunkReader {
+            data: html.as_bytes(),
+            chunk_size,
+        },
+        &mut output,
+        options,
+    )
+    .unwrap();
+
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn same_as_auto_p() {
+    let htmls = [
+        "",
+        "\n\n",
+        "Hello world!",
+        "Line 1\r\nLine 2\r\n\r\nLine 3",
+        "Paragraph 1\n\n\n\nParagraph 2\n\n",
+        "<section data=\"Hello, world!\n\nHiHiHi!\" ></section>",
+        "<div>\n\nRust Belt Rust – a rustlang conference\n\n<ul>\n    <li>2019: Dayton, \
+         Ohio</li>\n\n    <li>2018: Ann Arbor, Michigan</li>\n</ul>\n\n</div>",
+        "Text\n\n<pre>\n\t\t 123\n\n\t\t456<br><br>789  \n\t</pre>\n\nText",
+        "<script>alert('Hello');\n\nalert('World');</script>\n\n<p>Hello</p>",
+        "<pre>never closed\n\nText <b>bold</b>",
+        "3 < 5\n\n6 > 2",
+    ];
+
+    for html in htmls.iter() {
+        for chunk_size in [1, 2, 3, 7, 8192] {
+            for options in [
+                Options::new(),
+                Options::new().br(true),
+                Options::new().br(true).esc_pre(true).remove_useless_newlines_in_pre(true),
+            ] {
+                assert_eq!(
+                    auto_p(*html, options.clone()),
+                    auto_p_stream_to_string(html, chunk_size, options),
+                    "{:?}",
+                    html
+                );
+            }
+        }
+    }
+}
+
+/// A reader which checks how much has been written whenever it is read.
+struct CheckingReader {
+    chunks:  Vec<(&'static str, &'static str)>,
+    written: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Read for CheckingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.chunks.is_empty() {
+            return Ok(0);
+        }
+
+        let (expected_written, chunk) = self.chunks.remove(0);
+
+        assert_eq!(expected_written.as_bytes(), self.written.borrow().as_slice());
+
+        buf[..chunk.len()].copy_from_slice(chunk.as_bytes());
+
+        Ok(chunk.len())
+    }
+}
+
+struct SharedWriter(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn write_paragraphs_early() {
+    let written = Rc::new(RefCell::new(Vec::new()));
+
+    auto_p_stream(
+        CheckingReader {
+            chunks:  vec![
+                ("", "Paragraph 1\n"),
+                ("", "\nParagraph "),
+                ("<p>Paragraph 1</p>", "2\n\n<pre>\n\n"),
+                ("<p>Paragraph 1</p>\n<p>Paragraph 2</p>", "</pre>"),
+            ],
+            written: written.clone(),
+        },
+        SharedWriter(written.clone()),
+        Options::new(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        "<p>Paragraph 1</p>\n<p>Paragraph 2</p>\n<pre>\n\n</pre>",
+        String::from_utf8(written.take()).unwrap()
+    );
+}
+
+#[test]
+fn invalid_utf8() {
+    let error = auto_p_stream(&b"Hello\xFF"[..], io::sink(), Options::new()).unwrap_err();
+
+    assert_eq!(ErrorKind::InvalidData, error.kind());
+}
@@ -1,3 +1,10 @@
+use std::{
+    borrow::Cow,
+    fmt::Write as _,
+    io::Write as _,
+    time::{Duration, Instant},
+};
+
 use html_auto_p::*;
 
 #[test]
@@ -92,6 +99,46 @@ fn in_block_basic() {
     );
 }
 
+#[test]
+fn malformed() {
+    for (expected, html) in [
+        ("<p>&amp;&amp; <br/></li><span></p>", "&amp;&amp; <br/></li><span>"),
+        ("<p></em><table> <br>a</p>", "</em><table> <br>a"),
+        ("<div class=\"x\">\t\n<p>b</p>", "<div class=\"x\">\t<p>\n\nb"),
+        ("<p><em>y</em></p>\n</h1>", "<em>y</em><p>\n\n</p>\n</h1>"),
+    ] {
+        assert_eq!(expected, auto_p(html, Options::new()));
+        assert_eq!(expected, auto_p(expected, Options::new()));
+    }
+}
+
+#[test]
+fn malformed_p_tags() {
+    // where the output differs from the regex replaces of earlier versions, which left the added `<p>` tags unbalanced
+    for br in [false, true] {
+        let options = Options::new().br(br);
+
+        for (expected, html) in [
+            // a paragraph in a list item is closed
+            ("<li><p>a</p>", "<li>a"),
+            // a stray `<p>` or `</p>` is merged with the added tags
+            ("<p>a</p>", "a<p>"),
+            ("<p>a</p>", "</p>a"),
+            ("<p>b c</p>", "</p> \nb c"),
+            // so a stray tag without any content is removed like an empty paragraph
+            ("", "<p>"),
+            ("", "</p> "),
+            ("", "<p></p>"),
+        ] {
+            assert_eq!(expected, auto_p(html, options.clone()));
+        }
+    }
+
+    // the `</p>` of an empty list item is not left behind
+    assert_eq!("<li><br>\n<li>", auto_p("<li><br>\n\n<li>", Options::new()));
+    assert_eq!("<li>\n<li>", auto_p("<li><br>\n\n<li>", Options::new().br(true)));
+}
+
 #[test]
 fn reserve_newline_in_tag() {
     assert_eq!(
@@ -176,3 +223,1002 @@ fn in_block_script() {
         )
     );
 }
+
+#[test]
+fn textarea() {
+    assert_eq!(
+        "<textarea>Hello\n\nWorld</textarea>",
+        auto_p("<textarea>Hello\n\nWorld</textarea>", Options::new().br(true))
+    );
+    assert_eq!(
+        "<section><textarea>Hello\n\nWorld</textarea></section>",
+        auto_p("<section><textarea>Hello\n\nWorld</textarea></section>", Options::new().br(true))
+    );
+}
+
+#[test]
+fn cow() {
+    assert!(matches!(auto_p_cow("", Options::new()), Cow::Borrowed("")));
+    assert!(matches!(
+        auto_p_cow("<p>12345</p>\n<p>6789</p>", Options::new().br(true)),
+        Cow::Borrowed("<p>12345</p>\n<p>6789</p>")
+    ));
+    assert!(matches!(
+        auto_p_cow("\n\t<section>\n\t12345\n</section>\n\n", Options::new()),
+        Cow::Borrowed("<section>\n\t12345\n</section>")
+    ));
+    assert!(matches!(
+        auto_p_cow("<pre>\n1\n</pre>", Options::new()),
+        Cow::Borrowed("<pre>\n1\n</pre>")
+    ));
+
+    assert_eq!(
+        Cow::<str>::Owned(String::from("<p>12345</p>")),
+        auto_p_cow("12345", Options::new())
+    );
+    assert_eq!(
+        Cow::<str>::Owned(String::from("<p>12345<br>\n6789</p>")),
+        auto_p_cow("<p>12345\n6789</p>", Options::new().br(true))
+    );
+    assert_eq!(
+        Cow::<str>::Owned(String::from("<pre>1</pre>")),
+        auto_p_cow("<pre>\n1\n</pre>", Options::new().remove_useless_newlines_in_pre(true))
+    );
+}
+
+#[test]
+fn to() {
+    let mut html = String::from("<article>");
+
+    auto_p_to("12345\n6789", Options::new().br(true), &mut html).unwrap();
+    auto_p_to("", Options::new().br(true), &mut html).unwrap();
+
+    html.push_str("</article>");
+
+    assert_eq!("<article><p>12345<br>\n6789</p></article>", html);
+}
+
+#[test]
+fn display() {
+    let lazy = AutoP::new(
+        "12345\n\n<pre>\n6789\n</pre>",
+        Options::new().remove_useless_newlines_in_pre(true),
+    );
+
+    let mut html = String::new();
+
+    write!(html, "<article>{}</article>", lazy).unwrap();
+
+    assert_eq!("<article><p>12345</p>\n<pre>6789</pre></article>", html);
+
+    let mut bytes = Vec::new();
+
+    write!(bytes, "<article>{}</article>", lazy).unwrap();
+
+    assert_eq!(html.as_bytes(), bytes.as_slice());
+    assert_eq!(
+        auto_p("12345\n\n<pre>\n6789\n</pre>", Options::new()),
+        AutoP::new("12345\n\n<pre>\n6789\n</pre>", Options::new()).to_string()
+    );
+}
+
+#[test]
+fn remove_p_basic() {
+    assert_eq!("", remove_p("", RemovePOptions::new()));
+    assert_eq!("12345", remove_p("<p>12345</p>", RemovePOptions::new()));
+    assert_eq!("12345\n\n6789", remove_p("<p>12345</p>\n<p>6789</p>", RemovePOptions::new()));
+    assert_eq!("12345\n6789", remove_p("<p>12345<br>\n6789</p>", RemovePOptions::new().br(true)));
+    assert_eq!("12345<br>\n6789", remove_p("<p>12345<br>\n6789</p>", RemovePOptions::new()));
+    assert_eq!("12345<br>6789", remove_p("<p>12345<br>6789</p>", RemovePOptions::new().br(true)));
+    assert_eq!(
+        "<p class=\"x\">12345</p>",
+        remove_p("<p class=\"x\">12345</p>", RemovePOptions::new())
+    );
+    assert_eq!(
+        "<div>\n\n12345\n\n</div>",
+        remove_p("<div>\n<p>12345</p>\n</div>", RemovePOptions::new())
+    );
+    assert_eq!(
+        "<div><p>12345</p></div>",
+        remove_p("<div><p>12345</p></div>", RemovePOptions::new())
+    );
+    assert_eq!(
+        "<blockquote><p>q</p>\n\nr</blockquote>",
+        remove_p("<blockquote><p>q</p>\n<p>r</p></blockquote>", RemovePOptions::new())
+    );
+    // a `<p>` is removed only with its `</p>`
+    assert_eq!("a\n<p>b</p>c", remove_p("a\n<p>b</p>c", RemovePOptions::new()));
+    assert_eq!("a\n\nb\n\nc", remove_p("<p>a\n<p>b\n<p>c", RemovePOptions::new()));
+}
+
+#[test]
+fn remove_p_preserved() {
+    assert_eq!(
+        "<pre>\n<p>1</p>\n<p>2</p>\n</pre>",
+        remove_p("<pre>\n<p>1</p>\n<p>2</p>\n</pre>", RemovePOptions::new().br(true))
+    );
+    assert_eq!(
+        "<textarea>1<br>\n2</textarea>",
+        remove_p("<textarea>1<br>\n2</textarea>", RemovePOptions::new().br(true))
+    );
+}
+
+#[test]
+fn remove_p_round_trip() {
+    let text = [
+        "Line 1\nLine 2\n\n\n\nParagraph 2",
+        "<div>\n\nInner <b>bold</b>\n\n</div>",
+        "<pre>\na\n\nb\n</pre>",
+        "<ul>\n<li>Item</li>\n</ul>",
+        "Last",
+    ]
+    .join("\n\n");
+
+    for br in [false, true] {
+        for remove_useless_newlines_in_pre in [false, true] {
+            let options = Options::new()
+                .br(br)
+                .remove_useless_newlines_in_pre(remove_useless_newlines_in_pre);
+
+            let html = auto_p(text.as_str(), options.clone());
+
+            assert_eq!(html, auto_p(remove_p(&html, RemovePOptions::new().br(br)), options));
+        }
+    }
+}
+
+#[test]
+fn engine() {
+    let engine = AutoPEngine::builder().block_tags(["callout", "X-Card"]).build();
+
+    assert_eq!(
+        "<p><callout>12345</callout></p>",
+        auto_p("<callout>12345</callout>", Options::new())
+    );
+    assert_eq!("<callout>12345</callout>", engine.auto_p("<callout>12345</callout>"));
+    assert_eq!(
+        "<x-card><p>12345</p></x-card>",
+        engine.auto_p("<X-CARD>12345</p></X-CARD>").to_lowercase()
+    );
+    assert_eq!(
+        "<x-card>\n<p>12345</p>\n</x-card>",
+        engine.auto_p("<x-card>\n\n12345\n\n</x-card>")
+    );
+    assert_eq!("<div>\n<p>12345</p>\n</div>", engine.auto_p("<div>\n\n12345\n\n</div>"));
+
+    let engine =
+        AutoPEngine::builder().options(Options::new().br(true)).block_tags(["callout"]).build();
+
+    assert_eq!(
+        "<callout>\n12345<br>\n6789\n</callout>",
+        engine.auto_p("<callout>\n12345\n6789\n</callout>")
+    );
+    assert_eq!(
+        "<callout>\n<p>12345<br>\n6789</p>\n</callout>",
+        engine.display("<callout>\n\n12345\n6789\n\n</callout>").to_string()
+    );
+}
+
+#[test]
+fn engine_preserved_tags() {
+    let engine = AutoPEngine::builder()
+        .options(Options::new().br(true))
+        .preserved_tag("code", PreservedTagOptions::new().esc(true))
+        .preserved_tag("template", PreservedTagOptions::new().remove_useless_newlines(true))
+        .build();
+
+    // a registered preserved element is not wrapped in a paragraph, like `<textarea>`
+    assert_eq!(
+        "<code>a &lt; b\n\nc</code>\n<p>12345</p>",
+        engine.auto_p("<code>a < b\n\nc</code>\n\n12345")
+    );
+    assert_eq!(
+        "<template><p>1</p>\n\n<p>2</p></template>",
+        engine.auto_p("<template>\n<p>1</p>\n\n<p>2</p>\n</template>")
+    );
+    assert_eq!(
+        "<p>a</p>\n<template>b</template>\n<p>c</p>",
+        engine.auto_p("a\n\n<template>b</template>\n\nc")
+    );
+    assert_eq!(
+        "<p><code>a<br>\nb</code></p>",
+        auto_p("<code>a\nb</code>", Options::new().br(true))
+    );
+
+    // the options of a built-in preserved element can be replaced
+    let engine = AutoPEngine::builder()
+        .options(Options::new().esc_pre(true))
+        .preserved_tag("PRE", PreservedTagOptions::new().remove_useless_newlines(true))
+        .build();
+
+    assert_eq!("<pre><b>1</b></pre>", engine.auto_p("<pre>\n<b>1</b>\n</pre>"));
+}
+
+#[test]
+fn custom_elements() {
+    let html = "<my-gallery>\n\n<img src=\"1.png\">\n\n</my-gallery>";
+
+    assert_eq!(
+        "<p><my-gallery></p>\n<p><img src=\"1.png\"></p>\n<p></my-gallery></p>",
+        auto_p(html, Options::new())
+    );
+    assert_eq!(
+        "<my-gallery>\n<p><img src=\"1.png\"></p>\n</my-gallery>",
+        auto_p(html, Options::new().custom_elements(CustomElementPolicy::Block))
+    );
+    assert_eq!(
+        "<my-gallery>\n\n<img src=\"1.png\">\n\n</my-gallery>",
+        auto_p(html, Options::new().custom_elements(CustomElementPolicy::Preserved))
+    );
+    assert_eq!(
+        "<p><X-Alert>12345</X-Alert></p>",
+        auto_p("<X-Alert>12345</X-Alert>", Options::new())
+    );
+    assert_eq!(
+        "<X-Alert>12345</X-Alert>",
+        auto_p(
+            "<X-Alert>12345</X-Alert>",
+            Options::new().custom_elements(CustomElementPolicy::Block)
+        )
+    );
+
+    let engine = AutoPEngine::builder()
+        .options(Options::new().custom_elements(CustomElementPolicy::Preserved))
+        .block_tags(["my-card"])
+        .inline_tags(["my-icon"])
+        .build();
+
+    assert_eq!(
+        "<my-card>\n<p>12345</p>\n</my-card>",
+        engine.auto_p("<my-card>\n\n12345\n\n</my-card>")
+    );
+    assert_eq!("<p><my-icon>12345</my-icon></p>", engine.auto_p("<my-icon>12345</my-icon>"));
+    assert_eq!(
+        "<my-gallery>\n\n12345\n\n</my-gallery>",
+        engine.auto_p("<my-gallery>\n\n12345\n\n</my-gallery>")
+    );
+}
+
+#[test]
+fn comment() {
+    assert_eq!("<!-- note -->", auto_p("<!-- note -->", Options::new().br(true)));
+    assert_eq!(
+        "<!-- note -->\n<p>12345</p>",
+        auto_p("<!-- note -->\n12345", Options::new().br(true))
+    );
+    assert_eq!(
+        "<p>12345</p>\n<!-- a -->\n<!-- b -->",
+        auto_p("12345\n<!-- a -->\n<!-- b -->", Options::new().br(true))
+    );
+    assert_eq!(
+        "<!-- 12345\n\n6789 -->\n<p>12345</p>",
+        auto_p("<!-- 12345\n\n6789 -->\n\n12345", Options::new().br(true))
+    );
+    assert_eq!(
+        "<p>12345 <!-- note --> 6789</p>",
+        auto_p("12345 <!-- note --> 6789", Options::new().br(true))
+    );
+    assert_eq!(
+        "<p>12345<br>\n<!-- note -->\n6789</p>",
+        auto_p("12345\n<!-- note -->\n6789", Options::new().br(true))
+    );
+    assert_eq!(
+        "<div>\n<!-- note -->\n</div>",
+        auto_p("<div>\n<!-- note -->\n</div>", Options::new().br(true))
+    );
+    assert_eq!(
+        "<!-- 12345\n\n6789 -->",
+        remove_p("<!-- 12345\n\n6789 -->", RemovePOptions::new().br(true))
+    );
+}
+
+#[test]
+fn shortcode() {
+    let engine = AutoPEngine::builder()
+        .options(Options::new().br(true))
+        .shortcodes(["gallery", "caption"])
+        .build();
+
+    assert_eq!("<p>[gallery]</p>", auto_p("[gallery]", Options::new().br(true)));
+    assert_eq!("[gallery ids=\"1,2\"]", engine.auto_p("[gallery ids=\"1,2\"]"));
+    assert_eq!(
+        "<p>12345</p>\n[gallery ids=\"1,\n2\"]\n<p>6789</p>",
+        engine.auto_p("12345\n\n[gallery ids=\"1,\n2\"]\n\n6789")
+    );
+    assert_eq!(
+        "[caption]<img src=\"1.png\">\n12345[/caption]",
+        engine.auto_p("[caption]<img src=\"1.png\">\n12345[/caption]")
+    );
+    assert_eq!(
+        "[caption]\n<p>12345</p>\n[/caption]",
+        engine.auto_p("[caption]\n\n12345\n\n[/caption]")
+    );
+    assert_eq!("[gallery]\n<p>12345</p>", engine.auto_p("[gallery]\n12345"));
+    assert_eq!("<p>12345 [gallery]<br>\n6789</p>", engine.auto_p("12345 [gallery]\n6789"));
+    assert_eq!("<p>[[gallery]]</p>", engine.auto_p("[[gallery]]"));
+    assert_eq!("<p>[Gallery]</p>", engine.auto_p("[Gallery]"));
+}
+
+#[test]
+fn gutenberg() {
+    let options = Options::new().gutenberg(true);
+
+    let html = "<!-- wp:heading {\"level\":3} -->\n<h3>Title</h3>\n<!-- /wp:heading -->\n\n<!-- \
+                wp:group -->\n<div>\n\n<!-- wp:paragraph -->\n<p>12345\n6789</p>\n<!-- \
+                /wp:paragraph -->\n\n<!-- wp:spacer /--></div>\n<!-- /wp:group -->";
+
+    assert_eq!(
+        html.replacen("-->\n\n<!-- wp:group", "-->\n<!-- wp:group", 1),
+        auto_p(html, options.clone())
+    );
+    assert_eq!(
+        "<p>12345<br>\n6789</p>\n<!-- wp:separator /-->\n<p>abc</p>\n<!-- wp:paragraph \
+         -->\n<p>a\n\nb</p>\n<!-- /wp:paragraph -->",
+        auto_p(
+            "12345\n6789\n\n<!-- wp:separator /-->\nabc\n\n\n\n<!-- wp:paragraph \
+             -->\n<p>a\n\nb</p>\n<!-- /wp:paragraph -->",
+            options.clone().br(true)
+        )
+    );
+    assert_eq!(
+        "<!-- wp:html -->\n<p>abc</p>\n<!-- /wp:html -->",
+        auto_p("<!-- wp:html -->\nabc\n<!-- /wp:html -->", Options::new())
+    );
+    assert_eq!(
+        "<!-- wp:html -->\nabc\n<!-- /wp:html -->",
+        auto_p("<!-- wp:html -->\nabc\n<!-- /wp:html -->", options.clone())
+    );
+    assert_eq!(
+        "<!-- /wp:paragraph -->\n<p>12345</p>",
+        auto_p("<!-- /wp:paragraph -->\n\n12345", options.clone())
+    );
+    assert_eq!(
+        "<!-- wp:paragraph -->\n\n12345\n\n6789",
+        auto_p("<!-- wp:paragraph -->\n\n12345\n\n6789", options)
+    );
+
+    let blocks = parse_blocks(html);
+
+    assert_eq!(2, blocks.len());
+    assert_eq!(Some("core/heading"), blocks[0].name.as_deref());
+    assert_eq!(Some("{\"level\":3}"), blocks[0].attributes);
+    assert_eq!(Some("core/group"), blocks[1].name.as_deref());
+    assert_eq!(None, blocks[1].attributes);
+    assert!(blocks[1].raw.ends_with("<!-- /wp:group -->"));
+    assert_eq!(
+        vec![Some("core/paragraph"), Some("core/spacer")],
+        blocks[1].inner_blocks.iter().map(|e| e.name.as_deref()).collect::<Vec<_>>()
+    );
+
+    let blocks = parse_blocks("Classic\n\n<!-- wp:my-plugin/card {\"id\":1} /-->");
+
+    assert!(blocks[0].is_freeform());
+    assert_eq!("Classic", blocks[0].raw);
+    assert_eq!(Some("my-plugin/card"), blocks[1].name.as_deref());
+    assert_eq!(Some("{\"id\":1}"), blocks[1].attributes);
+}
+
+#[test]
+fn select_option() {
+    let options = Options::new().br(true);
+
+    assert_eq!(
+        "<p>Choose:<br>\n<select name=\"a\"><option value=\"1\">One</option><optgroup \
+         label=\"b\"><option>Two</option></optgroup></select></p>",
+        auto_p(
+            "Choose:\n<select name=\"a\">\n  <option value=\"1\">One</option>\n\n  <optgroup \
+             label=\"b\">\n    <option>Two</option>\n  </optgroup>\n</select>",
+            options.clone()
+        )
+    );
+    assert_eq!(
+        "<p><option>One</option><br>\n<option>Two</option></p>",
+        auto_p("<option>One</option>\n<option>Two</option>", options)
+    );
+}
+
+#[test]
+fn object_param_embed() {
+    assert_eq!(
+        "<p><object width=\"400\"><param name=\"movie\" value=\"a.swf\"><embed src=\"a.swf\" \
+         width=\"400\"></object></p>\n<p>12345</p>",
+        auto_p(
+            "<object width=\"400\">\n  <param name=\"movie\" value=\"a.swf\">\n\n  <embed \
+             src=\"a.swf\" width=\"400\">\n</object>\n\n12345",
+            Options::new().br(true)
+        )
+    );
+    assert_eq!(
+        "<p><embed src=\"a.swf\"><br>\n12345</p>",
+        auto_p("<embed src=\"a.swf\">\n12345", Options::new().br(true))
+    );
+}
+
+#[test]
+fn audio_video_source_track() {
+    let options = Options::new().br(true);
+
+    assert_eq!(
+        "<p><video controls><source src=\"a.mp4\" type=\"video/mp4\"><track \
+         src=\"a.vtt\"></video></p>\n<p>12345</p>",
+        auto_p(
+            "<video controls>\n  <source src=\"a.mp4\" type=\"video/mp4\">\n\n  <track \
+             src=\"a.vtt\">\n</video>\n\n12345",
+            options.clone()
+        )
+    );
+    assert_eq!(
+        "<p><audio controls><source src=\"a.mp3\">No audio.</audio></p>",
+        auto_p("<audio controls>\n<source src=\"a.mp3\">\nNo audio.\n</audio>", options.clone())
+    );
+    assert_eq!(
+        "<p><audio controls><source src=\"a.mp3\"></audio></p>",
+        auto_p("<audio controls>\n\n<source src=\"a.mp3\">\n\n</audio>", options)
+    );
+}
+
+#[test]
+fn plain_text() {
+    let options = Options::new().plain_text(true);
+
+    assert_eq!(
+        "<p>&lt;div&gt;</p>\n<p>&lt;script&gt;alert(&quot;1&quot;)&lt;/script&gt;</p>",
+        auto_p("<div>\n\n<script>alert(\"1\")</script>", options.clone())
+    );
+    assert_eq!(
+        "<p>Tom &amp; Jerry&#x27;s<br>\n&lt;!-- 12345 --&gt;</p>\n<p>&lt;/p&gt;</p>",
+        auto_p("Tom & Jerry's\r\n<!-- 12345 -->\r\n\r\n</p>", options.clone().br(true))
+    );
+    assert_eq!(
+        "<p>[gallery]</p>",
+        AutoPEngine::builder().options(options).shortcodes(["gallery"]).build().auto_p("[gallery]")
+    );
+}
+
+#[test]
+fn linkify() {
+    let engine = AutoPEngine::builder()
+        .options(Options::new().br(true))
+        .linkify(LinkifyOptions::new().rel("nofollow").target("_blank"))
+        .build();
+
+    assert_eq!(
+        "<p>See <a href=\"https://example.com/?a=1&amp;b=2\" rel=\"nofollow\" target=\"_blank\">https://example.com/?a=1&amp;b=2</a>.<br>\n(<a href=\"http://www.example.com/x_(y)\" rel=\"nofollow\" target=\"_blank\">www.example.com/x_(y)</a>), <a href=\"mailto:me@example.com\">me@example.com</a></p>",
+        engine.auto_p("See https://example.com/?a=1&amp;b=2.\n(www.example.com/x_(y)), me@example.com")
+    );
+    assert_eq!(
+        "<p><a href=\"https://example.com\">https://example.com</a> <img src=\"https://example.com/1.png\"> <!-- https://example.com --></p>\n<pre>https://example.com</pre>",
+        engine.auto_p("<a href=\"https://example.com\">https://example.com</a> <img src=\"https://example.com/1.png\"> <!-- https://example.com -->\n\n<pre>https://example.com</pre>")
+    );
+    assert_eq!(
+        "<p>abchttps://example.com me@localhost http://</p>",
+        engine.auto_p("abchttps://example.com me@localhost http://")
+    );
+
+    let engine = AutoPEngine::builder()
+        .options(Options::new().plain_text(true))
+        .linkify(LinkifyOptions::new())
+        .build();
+
+    assert_eq!(
+        "<p>&lt;b&gt;<a href=\"https://example.com/?a=1&amp;b=2\">https://example.com/?a=1&amp;b=2</a>&lt;/b&gt;</p>",
+        engine.auto_p("<b>https://example.com/?a=1&b=2</b>")
+    );
+}
+
+#[test]
+fn xhtml() {
+    let options = Options::new().br(true).xhtml(true);
+
+    assert_eq!(
+        "<p>12345<br />\n6789<br />\n<img src=\"1.png?a=1&amp;b=2\" alt=\"&#233;\" /><input \
+         type=\"text\" /></p><hr />",
+        auto_p(
+            "12345<br/>\n6789\n<img src=\"1.png?a=1&b=2\" alt=\"&eacute;\"><input \
+             type=\"text\"/><hr>",
+            options.clone()
+        )
+    );
+    assert_eq!(
+        "<p>&#160;&#169; &amp; &lt;&gt; &quot;&apos; &#169;&#xA9; Tom &amp; Jerry \
+         &amp;unknown;</p>",
+        auto_p(
+            "&nbsp;&copy; &amp; &lt;&gt; &quot;&apos; &#169;&#xA9; Tom & Jerry &unknown;",
+            options.clone()
+        )
+    );
+    assert_eq!("<pre>&#160;\n\n</pre>", auto_p("<pre>&nbsp;\n\n</pre>", options.clone()));
+    assert_eq!("<script>a && b</script>", auto_p("<script>a && b</script>", options.clone()));
+    assert_eq!("<p>a</p>\n<p>b</p></div><p>c</p>", auto_p("<p>a\n\n<p>b</div>c", options));
+}
+
+#[test]
+fn xhtml_well_formed() {
+    let options = Options::new().br(true).xhtml(true);
+
+    assert_eq!("<p>3 &lt; 5 &amp;&amp; 5 &gt; 3</p>", auto_p("3 < 5 && 5 > 3", options.clone()));
+    assert_eq!("<pre><br />a &lt; b</pre>", auto_p("<pre><br>a < b</pre>", options.clone()));
+    assert_eq!(
+        "<textarea>&lt;br&gt;</textarea>",
+        auto_p("<textarea><br></textarea>", options.clone())
+    );
+    assert_eq!("<p>a</p>\n<div>b</div>", auto_p("<P>a</P>\n\n<DIV>b</DIV>", options.clone()));
+    assert_eq!(
+        "<p><img src=\"x\" /><br />\n<input disabled=\"disabled\" class=\"a\" /></p>",
+        auto_p("<img src=x>\n<input disabled class=a CLASS=b>", options.clone())
+    );
+    assert_eq!(
+        "<p title=\"say &quot;hi&quot; &lt;b>\">x</p>",
+        auto_p("<p title='say \"hi\" <b>'>x</p>", options.clone())
+    );
+    assert_eq!("<p>a &#xFFFD; b&#38;\u{FFFD}c</p>", auto_p("a &#0; b&#38;\u{1}c", options.clone()));
+    assert_eq!(
+        "<!-- a - - b - -->\n<p><!---->x</p>",
+        auto_p("<!-- a -- b --->\n\n<!--->x", options.clone())
+    );
+
+    let inputs = [
+        "3 < 5\n\n<pre><br>a < b <HR></pre>",
+        "<P CLASS=x>a</P>\n<UL>\n<LI>b</LI>\n<LI>c</li>\n</UL>",
+        "<img src=x alt=a&b>\n<input disabled checked>\n<input type=text value=\"<>\" value=y>",
+        "<textarea>\n<b>a</b> & b\n</textarea>\n\n&nbsp;&copy; <a href=https://example.com>x</a>",
+        "<table>\n<tr><td>a<br>b</td></tr>\n</table>\n\n<div>c\n\nd</div>",
+        "<blockquote>a]]>b\n<!-- c -->\n<pre>\n<img src=1.png>\n</pre></blockquote>",
+        "a &#0; b &#x1; c &#xD800; d &#99999999999; e\n\n<a title=\"&#0;\u{1}\">f</a>",
+        "x\u{1}y\u{b}z\u{FFFF}\n\n<pre>\u{1}</pre>",
+        "<!-- a -- b -->\n\n<!-- c --->\n\nd <!-- e -- f -->",
+        "<!--->x\n\n<!-->y\n\n<pre><!-- a -- b --></pre>\n\n<!-- unclosed -",
+    ];
+
+    for input in inputs.iter() {
+        for options in [
+            options.clone(),
+            Options::new().xhtml(true),
+            Options::new().br(true).xhtml(true).pretty(2),
+            Options::new().br(true).xhtml(true).compact(true),
+        ] {
+            let output = format!("<root>{}</root>", auto_p(*input, options));
+
+            if let Err(error) = roxmltree::Document::parse(&output) {
+                panic!("{}: {:?}", error, output);
+            }
+        }
+    }
+}
+
+#[test]
+fn paragraph_element() {
+    let options = Options::new().br(true).paragraph(
+        ParagraphElement::new()
+            .tag_name("div")
+            .attribute("class", "para")
+            .attribute("style", "margin: 0")
+            .first_class("lead")
+            .nth_class(2, "second")
+            .last_class("end"),
+    );
+
+    assert_eq!(
+        "<div class=\"para lead\" style=\"margin: 0\">12345</div>\n<div class=\"para second\" \
+         style=\"margin: 0\">6789</div>\n<p class=\"user\">abc</p>\n<ul>\n<li>a</li>\n</ul>\n<div \
+         class=\"para end\" style=\"margin: 0\">xyz<br>\nend</div>",
+        auto_p(
+            "12345\n\n6789\n\n<p class=\"user\">abc</p>\n\n<ul>\n<li>a</li>\n</ul>\n\n\n\nxyz\nend",
+            options.clone()
+        )
+    );
+    assert_eq!(
+        "<div class=\"para lead end\" style=\"margin: 0\">12345</div>",
+        auto_p("12345</p>", options.clone())
+    );
+
+    let mut output = Vec::new();
+
+    auto_p_stream("12345\n\n6789".as_bytes(), &mut output, options).unwrap();
+
+    assert_eq!(
+        "<div class=\"para lead\" style=\"margin: 0\">12345</div>\n<div class=\"para second end\" \
+         style=\"margin: 0\">6789</div>",
+        String::from_utf8(output).unwrap()
+    );
+    let options = Options::new().paragraph(ParagraphElement::new().tag_name("div"));
+
+    for html in ["<li>12345\n\n6789", "<div>\n\n</div>\n\n\n\n12345", "12345<div>6789</div>abc"] {
+        assert_eq!(
+            auto_p(html, Options::new()).replace("<p>", "<div>").replace("</p>", "</div>"),
+            auto_p(html, options.clone())
+        );
+    }
+
+    assert_eq!(
+        "<p style=\"color: &quot;red&quot;\">12345</p>",
+        auto_p(
+            "12345",
+            Options::new().paragraph(ParagraphElement::new().attribute("style", "color: \"red\""))
+        )
+    );
+
+    // an inline element cannot hold paragraphs
+    for name in ["span", "hr", "pre"] {
+        let options = Options::new().paragraph(ParagraphElement::new().tag_name(name));
+
+        assert_eq!(
+            "<p>12345</p>\n<p><span>6789</span></p>",
+            auto_p("12345\n\n<span>6789</span>", options.clone())
+        );
+        assert_eq!("<p><span>x</span> world</p>", auto_p("<span>x</span> world", options));
+    }
+}
+
+#[test]
+fn paragraph_element_names() {
+    let options = Options::new().paragraph(
+        ParagraphElement::new()
+            .tag_name("my-para")
+            .attribute("data-x", "1")
+            .attribute("@click", "f()"),
+    );
+
+    assert_eq!("<my-para data-x=\"1\" @click=\"f()\">a</my-para>", auto_p("a", options));
+
+    // the fields can be set without validation, and then the invalid names are not written
+    let mut paragraph = ParagraphElement::new();
+
+    paragraph.tag_name = "p onclick=alert(1)".into();
+    paragraph.attributes.push(("x\" onclick=\"y".to_string(), "z".to_string()));
+    paragraph.attributes.push(("title".to_string(), "t".to_string()));
+
+    assert_eq!("<p title=\"t\">a</p>", auto_p("a", Options::new().paragraph(paragraph)));
+}
+
+#[test]
+fn paragraph_element_invalid_names() {
+    let options = Options::new().paragraph(
+        ParagraphElement::new()
+            .tag_name("p onclick=alert(1)")
+            .attribute("x\" onclick=\"y", "z")
+            .attribute("title", "t"),
+    );
+
+    assert_eq!("<p title=\"t\">a</p>", auto_p("a", options));
+}
+
+#[test]
+fn pretty() {
+    let options = Options::new().pretty(2);
+
+    assert_eq!(
+        "<div>\n  <div>\n    x\n  </div>\n</div>",
+        auto_p("<div><div>x</div></div>", options.clone())
+    );
+    assert_eq!(
+        "<p>a</p>\n<blockquote>\n  <p>b\n  c</p>\n  <p>d</p>\n</blockquote>",
+        auto_p("a\n\n<blockquote>b\n   c\n\nd</blockquote>", options.clone())
+    );
+    assert_eq!(
+        "<div>\n  <pre>\n  a\n    b</pre><textarea>\n x</textarea>\n</div>",
+        auto_p("<div><pre>\n  a\n    b</pre><textarea>\n x</textarea></div>", options.clone())
+    );
+    assert_eq!(
+        "<p>a<br>\nb</p>\n<hr>\n<p>c</p>",
+        auto_p("a\nb\n\n<hr>\n\nc\n\n", options.br(true))
+    );
+
+    // deep nesting is indented like the 32nd level
+    let html = auto_p("<div>".repeat(40).as_str(), Options::new().pretty(1));
+
+    assert_eq!(Some(32), html.lines().map(|e| e.len() - e.trim_start().len()).max());
+}
+
+#[test]
+fn compact() {
+    let options = Options::new().compact(true);
+
+    assert_eq!(
+        "<p>a\nb</p><div>c</div><ul><li>1</li><li>2</li></ul>\n<!-- c -->\n<p>d<br>e</p>",
+        auto_p(
+            "a\nb\n\n<div>\nc\n</div>\n\n<ul>\n<li>1</li>\n<li>2</li>\n</ul>\n\n<!-- c \
+             -->\nd<br>\ne",
+            options.clone()
+        )
+    );
+    assert_eq!(
+        "<pre>\n  x\n\n y\n</pre><textarea>\n a\n</textarea>",
+        auto_p("<pre>\n  x\n\n y\n</pre>\n\n<textarea>\n a\n</textarea>", options.clone())
+    );
+    assert_eq!("<p>a <b>b</b><br><i>c</i></p>", auto_p("a <b>b</b>\n<i>c</i>", options.br(true)));
+}
+
+#[test]
+fn line_endings() {
+    assert_eq!(
+        "<pre>\r\na\r\n\r\nb\r\n</pre>\n<p>c\nd</p>",
+        auto_p("<pre>\r\na\r\n\r\nb\r\n</pre>\r\n\r\nc\r\nd", Options::new())
+    );
+
+    let options = Options::new().preserve_line_endings(true);
+
+    assert_eq!(
+        "<p>a\r\nb</p>\r\n<div>\r\nc\r\n</div>\r\n<pre>\r\n x\n</pre>\r\n<!-- a\n-->",
+        auto_p(
+            "a\r\nb\r\n\r\n<div>\r\nc\r\n</div>\r\n\r\n<pre>\r\n x\n</pre>\r\n\r\n<!-- a\n-->",
+            options.clone()
+        )
+    );
+    assert_eq!("<p>a<br>\rb</p>\r<p>c<br>\rd</p>", auto_p("a\rb\r\rc\nd", options.br(true)));
+}
+
+#[test]
+fn source_map() {
+    let input = "  a &amp; b\n\n<div>c\n<pre>\n\nd</pre></div>\n\nx < y";
+
+    let (html, source_map) = auto_p_with_map(input, Options::new().br(true));
+
+    assert_eq!(auto_p(input, Options::new().br(true)), html);
+
+    let mappings = source_map.mappings();
+
+    assert_eq!(4, mappings.len());
+
+    for mapping in mappings {
+        assert!(mapping.verbatim);
+        assert_eq!(&input[mapping.input.clone()], &html[mapping.output.clone()]);
+    }
+
+    assert_eq!(
+        Some(html.find("c\n<pre>").unwrap()),
+        source_map.output_offset(input.find('c').unwrap())
+    );
+    assert_eq!(
+        Some(input.find("\nd").unwrap()),
+        source_map.input_offset(html.find("\nd").unwrap())
+    );
+    assert_eq!(None, source_map.input_offset(html.find("<div>").unwrap()));
+    assert_eq!(None, source_map.output_offset(0));
+
+    let (html, source_map) = auto_p_with_map(
+        "<b>a</b>\n\n<i>b</i>",
+        Options::new().plain_text(true).paragraph(ParagraphElement::new().last_class("last")),
+    );
+
+    assert_eq!("<p>&lt;b&gt;a&lt;/b&gt;</p>\n<p class=\"last\">&lt;i&gt;b&lt;/i&gt;</p>", html);
+    assert_eq!(Some(html.rfind("&lt;i").unwrap()), source_map.output_offset(12));
+    assert_eq!(Some(10), source_map.input_offset(html.len() - 6));
+
+    // `&nbsp;` and `&#160;` have the same length, but the pieces are not mapped byte by byte
+    let (html, source_map) =
+        auto_p_with_map("a&nbsp;b\n\n<pre>&nbsp;c</pre>", Options::new().xhtml(true));
+
+    assert_eq!("<p>a&#160;b</p>\n<pre>&#160;c</pre>", html);
+    assert!(source_map.mappings().iter().all(|e| !e.verbatim));
+    assert_eq!(Some(0), source_map.input_offset(html.find('b').unwrap()));
+    assert_eq!(Some(3), source_map.output_offset(7));
+    assert_eq!(Some(15), source_map.input_offset(html.find('c').unwrap()));
+
+    let (html, source_map) = auto_p_with_map("a b", Options::new().xhtml(true));
+
+    assert!(source_map.mappings()[0].verbatim);
+    assert_eq!(Some(2), source_map.input_offset(html.find('b').unwrap()));
+}
+
+#[test]
+fn diagnostics() {
+    let kinds = |diagnostics: &[Diagnostic]| {
+        diagnostics.iter().map(|e| (e.kind, e.span.clone())).collect::<Vec<_>>()
+    };
+
+    let (html, diagnostics) =
+        auto_p_checked("<ul>\n<li>a\n<li>b\n</ul>\n\n<p>c</p>\n\n<!-- x -->", Options::new());
+
+    assert_eq!(auto_p("<ul>\n<li>a\n<li>b\n</ul>\n\n<p>c</p>\n\n<!-- x -->", Options::new()), html);
+    assert!(diagnostics.is_empty());
+
+    let (_, diagnostics) = auto_p_checked("a</div>\n\n<div><section>b</div>", Options::new());
+
+    assert_eq!(
+        vec![
+            (DiagnosticKind::StrayEndTag, 1..7),
+            (DiagnosticKind::UnclosedElement, 14..23),
+            (DiagnosticKind::UnbalancedOutput, 8..14),
+            (DiagnosticKind::UnbalancedOutput, 20..29),
+        ],
+        kinds(&diagnostics)
+    );
+    assert_eq!("`</div>` does not close any open element", diagnostics[0].message);
+
+    let (_, diagnostics) = auto_p_checked("<b>a<div>b</div></b>\n\n<pre>c", Options::new());
+
+    assert_eq!(
+        vec![
+            (DiagnosticKind::BlockInInline, 4..9),
+            (DiagnosticKind::UnclosedPreservedElement, 22..27)
+        ],
+        kinds(&diagnostics)
+    );
+
+    let (_, diagnostics) = auto_p_checked("a\n\n<!-- b", Options::new());
+
+    assert_eq!(vec![(DiagnosticKind::UnclosedComment, 3..9)], kinds(&diagnostics));
+}
+
+#[test]
+fn limits() {
+    let input = "<div><section>a\n\nb</section></div>\n\n<pre>1</pre><pre>2</pre>";
+
+    assert_eq!(Ok(auto_p(input, Options::new())), try_auto_p(input, Options::new(), Limits::new()));
+    assert_eq!(
+        Ok(auto_p(input, Options::new())),
+        try_auto_p(
+            input,
+            Options::new(),
+            Limits::new()
+                .max_input_length(input.len())
+                .max_output_growth(12)
+                .max_depth(2)
+                .max_preserved(2)
+        )
+    );
+    assert_eq!(
+        Err(AutoPError::InputTooLong {
+            length: input.len(), limit: 10
+        }),
+        try_auto_p(input, Options::new(), Limits::new().max_input_length(10))
+    );
+    assert_eq!(
+        Err(AutoPError::OutputTooLong {
+            limit: 11
+        }),
+        try_auto_p(input, Options::new(), Limits::new().max_output_growth(11))
+    );
+    assert_eq!(
+        Err(AutoPError::TooDeep {
+            limit: 1
+        }),
+        try_auto_p(input, Options::new(), Limits::new().max_depth(1))
+    );
+    assert_eq!(
+        Err(AutoPError::TooManyPreserved {
+            limit: 1
+        }),
+        try_auto_p(input, Options::new(), Limits::new().max_preserved(1))
+    );
+    assert_eq!(
+        Ok(String::from("<p><b>a</b></p>\n<p><i>b</i></p>")),
+        try_auto_p("<b>a</b>\n\n<i>b</i>", Options::new(), Limits::new().max_depth(1))
+    );
+
+    // elements whose end tags are omitted are closed by the following start tags
+    let input = format!(
+        "<ul>{}</ul>\n\n{}\n\n<dl>{}</dl>\n\n<table>{}</table>",
+        "<li>a<p>b<li>c".repeat(100),
+        "<p>a".repeat(100),
+        "<dt>a<dd>b".repeat(100),
+        "<tr><td>a<td>b".repeat(100),
+    );
+
+    assert_eq!(
+        Ok(auto_p(input.as_str(), Options::new())),
+        try_auto_p(input.as_str(), Options::new(), Limits::new().max_depth(3))
+    );
+    assert_eq!(
+        Err(AutoPError::TooDeep {
+            limit: 2
+        }),
+        try_auto_p(input.as_str(), Options::new(), Limits::new().max_depth(2))
+    );
+}
+
+#[test]
+fn hostile_input() {
+    let inputs: [fn(usize) -> String; 12] = [
+        |n| format!("<p a=\"{}", "b=\"c\" ".repeat(n)),
+        |n| format!("<div {}>", "a= ".repeat(n)),
+        |n| format!("<div {}>", (0..n).map(|i| format!("a{} ", i)).collect::<String>()),
+        |n| "<a b=c ".repeat(n),
+        |n| format!("{}</p>", "x\n".repeat(n)),
+        |n| "<".repeat(n * 5 / 2),
+        |n| "<!--".repeat(n),
+        |n| "<pre>".repeat(n),
+        |n| format!("{}{}", "<div>".repeat(n), "</div>".repeat(n)),
+        |n| "[gallery ".repeat(n),
+        |n| format!("https://x{}", ")".repeat(n * 4)),
+        |n| format!("{}x{}", "<!-- wp:a -->".repeat(n * 2), "<!-- /wp:a -->".repeat(n * 2)),
+    ];
+
+    // the fastest of a few runs, to keep the noise out
+    fn fastest(f: impl Fn()) -> Duration {
+        (0..3)
+            .map(|_| {
+                let start = Instant::now();
+
+                f();
+
+                start.elapsed()
+            })
+            .min()
+            .unwrap()
+    }
+
+    // 10 times the input takes about 10 times as long in linear time, and 100 times as long in quadratic time
+    fn assert_linear(small: Duration, large: Duration, input: &str) {
+        assert!(
+            large <= small.max(Duration::from_millis(1)) * 30,
+            "{:?} for the input of 2000 repetitions, but {:?} for 20000 repetitions: {:?}",
+            small,
+            large,
+            input
+        );
+    }
+
+    for input in inputs.iter() {
+        for options in [
+            Options::new(),
+            Options::new().br(true).xhtml(true),
+            Options::wordpress_compat(),
+            Options::new().pretty(2),
+        ] {
+            // the XHTML mode leaves out repeated attributes
+            let xhtml = options.xhtml;
+
+            let engine = AutoPEngine::builder()
+                .options(options)
+                .shortcodes(["gallery"])
+                .linkify(LinkifyOptions::new())
+                .build();
+
+            let time = |input: &str| {
+                fastest(|| {
+                    let output = engine.auto_p(input);
+
+                    assert!(xhtml || output.len() >= input.trim().len());
+                })
+            };
+
+            assert_linear(time(&input(2000)), time(&input(20000)), &input(1));
+        }
+
+        let (small, large) = (input(2000), input(20000));
+
+        assert_linear(
+            fastest(|| assert!(!parse_blocks(&small).is_empty())),
+            fastest(|| assert!(!parse_blocks(&large).is_empty())),
+            &input(1),
+        );
+    }
+}
+
+#[test]
+fn wordpress_compat() {
+    let options = Options::wordpress_compat();
+
+    assert_eq!("", auto_p(" \n\t", options.clone()));
+    assert_eq!("<p>Hello world!</p>\n", auto_p("Hello world!", options.clone()));
+    assert_eq!("<p>a<br />\nb</p>\n", auto_p("a\nb", options.clone()));
+    assert_eq!("<p>a\nb</p>\n", auto_p("a\nb", options.clone().br(false)));
+    assert_eq!(
+        "<p>Hello <a\nhref='world'></p>\n",
+        auto_p("Hello <a\nhref='world'>", options.clone())
+    );
+    assert_eq!(
+        "<p>Hello <!-- <object>\n<param>\n<embed>\n</embed>\n</object>\n --></p>\n",
+        auto_p("Hello <!-- <object>\n<param>\n<embed>\n</embed>\n</object>\n -->", options.clone())
+    );
+
+    // the quirks of `wpautop` are kept
+    assert_eq!("<div>a</p>\n<p>b</p></div>\n", auto_p("<div>a\n\nb</div>", options.clone()));
+    assert_eq!(
+        "<p>a</p>\n<p><b>b</p>\n<p>c</b></p>\n",
+        auto_p("a\n\n<b>b\n\nc</b>", options.clone())
+    );
+
+    let input = "a\n\n<pre>b\n\nc</pre>\nd";
+    let expected = "<p>a</p>\n<pre>b\n\nc</pre>\n<p>d</p>\n";
+
+    let mut output = Vec::new();
+
+    auto_p_stream(input.as_bytes(), &mut output, options.clone()).unwrap();
+
+    assert_eq!(expected, auto_p(input, options.clone()));
+    assert_eq!(expected, String::from_utf8(output).unwrap());
+    assert_eq!(expected, AutoP::new(input, options).to_string());
+}